@@ -0,0 +1,163 @@
+use crate::future::{in_fiber, wait};
+use crate::sync::oneshot;
+
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+const MAX_THREADS: usize = 512;
+// How long an idle thread waits for a new job before exiting.
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+struct State {
+    jobs: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+    // Wakeups handed to idle threads that they haven't consumed yet.
+    notified: usize,
+}
+
+struct Pool {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+impl Pool {
+    fn get() -> &'static Pool {
+        static POOL: OnceLock<Pool> = OnceLock::new();
+        POOL.get_or_init(|| Pool {
+            state: Mutex::new(State {
+                jobs: VecDeque::new(),
+                threads: 0,
+                idle: 0,
+                notified: 0,
+            }),
+            condvar: Condvar::new(),
+        })
+    }
+
+    fn spawn(&'static self, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        state.jobs.push_back(job);
+        if state.idle != 0 {
+            state.idle -= 1;
+            state.notified += 1;
+            self.condvar.notify_one();
+        } else if state.threads < MAX_THREADS {
+            state.threads += 1;
+            thread::Builder::new()
+                .name("stackful-blocking".into())
+                .spawn(move || self.run())
+                .expect("failed to spawn blocking thread");
+        }
+        // Otherwise the job is picked up once a thread finishes its current one.
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                drop(state);
+                job();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            state.idle += 1;
+            loop {
+                let (next, timeout) = self.condvar.wait_timeout(state, KEEP_ALIVE).unwrap();
+                state = next;
+                if state.notified != 0 {
+                    state.notified -= 1;
+                    break;
+                }
+                if timeout.timed_out() {
+                    state.idle -= 1;
+                    state.threads -= 1;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Run a blocking function without blocking the executor.
+///
+/// When called from a fiber, `f` is sent to a pool of threads dedicated to blocking calls, and the
+/// fiber waits until it completes. If the `tokio` feature is enabled and a tokio runtime is
+/// running, its blocking pool is used instead. Outside of a fiber, `f` is simply called on the
+/// current thread.
+///
+/// Panics in `f` are propagated to the caller.
+///
+/// ```
+/// # futures::executor::block_on(stackful::stackful(|| {
+/// let len = stackful::blocking(|| std::fs::read_to_string("Cargo.toml").unwrap().len());
+/// assert!(len > 0);
+/// # }));
+/// ```
+pub fn blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    if !in_fiber() {
+        return f();
+    }
+
+    #[cfg(feature = "tokio")]
+    if let Ok(handle) = tokio::runtime::Handle::try_current() {
+        return match wait(handle.spawn_blocking(f)) {
+            Ok(val) => val,
+            Err(err) if err.is_panic() => panic::resume_unwind(err.into_panic()),
+            Err(_) => panic!("blocking task cancelled by runtime shutdown"),
+        };
+    }
+
+    let (tx, rx) = oneshot::channel();
+    Pool::get().spawn(Box::new(move || {
+        let _ = tx.send(panic::catch_unwind(AssertUnwindSafe(f)));
+    }));
+    match wait(rx) {
+        Ok(Ok(val)) => val,
+        Ok(Err(payload)) => panic::resume_unwind(payload),
+        // The job is never dropped without being run.
+        Err(_) => unreachable!(),
+    }
+}
+
+#[test]
+fn test_blocking() {
+    use crate::{join, stackful, yield_now};
+    use std::cell::Cell;
+
+    let current = thread::current().id();
+    assert_eq!(blocking(move || thread::current().id()), current);
+
+    let progress = Cell::new(0);
+    let (id, ()) = futures_executor::block_on(join(
+        || {
+            blocking(|| {
+                thread::sleep(Duration::from_millis(50));
+                thread::current().id()
+            })
+        },
+        || {
+            // Keeps running while the other fiber is blocked.
+            for _ in 0..3 {
+                progress.set(progress.get() + 1);
+                yield_now();
+            }
+        },
+    ));
+    assert_ne!(id, current);
+    assert_eq!(progress.get(), 3);
+
+    let result = futures_executor::block_on(stackful(|| {
+        panic::catch_unwind(|| blocking(|| panic!("blocking panic")))
+    }));
+    assert!(result.is_err());
+}
@@ -0,0 +1,156 @@
+use crate::join::Either;
+use crate::wait;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Wait for all futures to complete, and return their outputs in order.
+///
+/// All futures are polled concurrently. Like `wait`, this blocks the thread if not called from a
+/// fiber.
+pub fn wait_all<I>(futs: I) -> Vec<<I::Item as Future>::Output>
+where
+    I: IntoIterator,
+    I::Item: Future,
+{
+    // The vector is never resized, so the futures are never moved.
+    let mut futs: Vec<_> = futs.into_iter().map(|fut| (fut, None)).collect();
+    wait(core::future::poll_fn(|cx| {
+        let mut done = true;
+        for (fut, output) in futs.iter_mut() {
+            if output.is_none() {
+                // SAFETY: This is safe because we don't move fut.
+                match unsafe { Pin::new_unchecked(fut) }.poll(cx) {
+                    Poll::Ready(val) => *output = Some(val),
+                    Poll::Pending => done = false,
+                }
+            }
+        }
+        if done {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }));
+    // Take the outputs and let the futures be dropped in place, as they are pinned.
+    futs.iter_mut()
+        .map(|(_, output)| output.take().unwrap())
+        .collect()
+}
+
+/// Wait for any of the futures to complete.
+///
+/// Returns the index and output of the future that completed first, along with the remaining
+/// futures so they can be waited for later. Panics if `futs` is empty.
+pub fn wait_any<I>(futs: I) -> (usize, <I::Item as Future>::Output, Vec<I::Item>)
+where
+    I: IntoIterator,
+    I::Item: Future + Unpin,
+{
+    let mut futs: Vec<_> = futs.into_iter().collect();
+    assert!(!futs.is_empty(), "`wait_any` called with no futures");
+    let (index, val) = wait(core::future::poll_fn(|cx| {
+        for (i, fut) in futs.iter_mut().enumerate() {
+            if let Poll::Ready(val) = Pin::new(fut).poll(cx) {
+                return Poll::Ready((i, val));
+            }
+        }
+        Poll::Pending
+    }));
+    drop(futs.remove(index));
+    (index, val, futs)
+}
+
+/// Future that polls two futures in order, and resolves with the first output.
+#[doc(hidden)]
+pub struct SelectFirst<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> SelectFirst<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self { a, b }
+    }
+}
+
+impl<A: Future, B: Future> Future for SelectFirst<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: Both futures are structurally pinned, and we never move them.
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Poll::Ready(val) = unsafe { Pin::new_unchecked(&mut this.a) }.poll(cx) {
+            return Poll::Ready(Either::Left(val));
+        }
+        if let Poll::Ready(val) = unsafe { Pin::new_unchecked(&mut this.b) }.poll(cx) {
+            return Poll::Ready(Either::Right(val));
+        }
+        Poll::Pending
+    }
+}
+
+/// Wait for the first of several futures to complete, and run the matching branch.
+///
+/// Futures are polled in order, and the ones that didn't complete are dropped. Like `wait`, this
+/// blocks the thread if not called from a fiber.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// # async_std::task::block_on(stackful::stackful(|| {
+/// let msg = stackful::wait_select! {
+///     _ = async_std::task::sleep(Duration::from_secs(10)) => "slow",
+///     n = async { 1 } => if n == 1 { "fast" } else { "wrong" },
+/// };
+/// assert_eq!(msg, "fast");
+/// # }));
+/// ```
+#[macro_export]
+macro_rules! wait_select {
+    (@fut $fut:expr) => {
+        $fut
+    };
+    (@fut $fut:expr, $($rest:expr),+) => {
+        $crate::__private::SelectFirst::new($fut, $crate::wait_select!(@fut $($rest),+))
+    };
+    (@match $val:expr; $pat:pat => $body:expr) => {
+        match $val {
+            $pat => $body,
+        }
+    };
+    (@match $val:expr; $pat:pat => $body:expr, $($rest_pat:pat => $rest_body:expr),+) => {
+        match $val {
+            $crate::Either::Left($pat) => $body,
+            $crate::Either::Right(rest) => $crate::wait_select!(@match rest; $($rest_pat => $rest_body),+),
+        }
+    };
+    ($($pat:pat = $fut:expr => $body:expr),+ $(,)?) => {
+        $crate::wait_select!(@match $crate::wait($crate::wait_select!(@fut $($fut),+)); $($pat => $body),+)
+    };
+}
+
+#[test]
+fn test_wait_all_any() {
+    use futures::future::{pending, ready};
+
+    assert_eq!(wait_all(vec![ready(1), ready(2)]), [1, 2]);
+
+    let futs: Vec<Pin<Box<dyn Future<Output = i32>>>> =
+        vec![Box::pin(pending()), Box::pin(ready(1)), Box::pin(ready(2))];
+    let (index, val, rest) = wait_any(futs);
+    assert_eq!((index, val, rest.len()), (1, 1, 2));
+}
+
+#[test]
+fn test_wait_select() {
+    use futures::future::{pending, ready};
+
+    let val = crate::wait_select! {
+        () = pending::<()>() => 0,
+        v = ready(2) => v,
+        v = ready(3) => v,
+    };
+    assert_eq!(val, 2);
+}
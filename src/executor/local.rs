@@ -0,0 +1,285 @@
+use super::{task, JoinHandle};
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+// Task ID used for the future passed to `block_on`.
+const MAIN: usize = usize::MAX;
+
+/// Queue of tasks that have been woken up. Wakers may be called from any thread.
+struct Queue {
+    ready: Mutex<VecDeque<usize>>,
+    condvar: Condvar,
+}
+
+impl Queue {
+    fn push(&self, id: usize) {
+        self.ready.lock().unwrap().push_back(id);
+        self.condvar.notify_one();
+    }
+
+    fn pop(&self) -> Option<usize> {
+        self.ready.lock().unwrap().pop_front()
+    }
+
+    // Block the thread until a task is woken.
+    fn park(&self) {
+        let ready = self.ready.lock().unwrap();
+        let _ready = self.condvar.wait_while(ready, |r| r.is_empty()).unwrap();
+    }
+}
+
+struct TaskWaker {
+    id: usize,
+    queue: Arc<Queue>,
+    // Avoid queueing a task multiple times if it's woken repeatedly before being polled.
+    queued: AtomicBool,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.queue.push(self.id);
+        }
+    }
+}
+
+struct Task {
+    fut: Pin<Box<dyn Future<Output = ()>>>,
+    waker: Arc<TaskWaker>,
+}
+
+struct Inner {
+    tasks: RefCell<Vec<Option<Task>>>,
+    free: RefCell<Vec<usize>>,
+    queue: Arc<Queue>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Rc<Inner>>> = const { RefCell::new(None) };
+}
+
+// Make an executor current for `spawn_local` until dropped.
+struct Enter(Option<Rc<Inner>>);
+
+impl Enter {
+    fn new(inner: &Rc<Inner>) -> Self {
+        Enter(CURRENT.with(|c| c.replace(Some(inner.clone()))))
+    }
+}
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        CURRENT.with(|c| *c.borrow_mut() = self.0.take());
+    }
+}
+
+impl Inner {
+    fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + 'static,
+        T: 'static,
+    {
+        let (fut, handle) = task(f);
+
+        let mut tasks = self.tasks.borrow_mut();
+        let id = self.free.borrow_mut().pop().unwrap_or_else(|| {
+            tasks.push(None);
+            tasks.len() - 1
+        });
+        let waker = Arc::new(TaskWaker {
+            id,
+            queue: self.queue.clone(),
+            queued: AtomicBool::new(false),
+        });
+        waker.wake_by_ref();
+        tasks[id] = Some(Task {
+            fut: Box::pin(fut),
+            waker,
+        });
+        handle
+    }
+
+    fn poll_task(&self, id: usize) {
+        // Take the task out, so it can spawn other tasks while being polled.
+        let mut task = match self.tasks.borrow_mut().get_mut(id).and_then(Option::take) {
+            Some(v) => v,
+            // Woken after completion.
+            None => return,
+        };
+        task.waker.queued.store(false, Ordering::Release);
+        let waker = Waker::from(task.waker.clone());
+        match task.fut.as_mut().poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(()) => self.free.borrow_mut().push(id),
+            Poll::Pending => self.tasks.borrow_mut()[id] = Some(task),
+        }
+    }
+}
+
+/// A single-threaded executor for stackful tasks.
+///
+/// Each task is a function running on its own fiber, which can call `wait` to suspend until a
+/// future completes, making the executor a self-contained green-thread runtime.
+///
+/// ```
+/// use stackful::executor::LocalExecutor;
+///
+/// let executor = LocalExecutor::new();
+/// let a = executor.spawn(|| {
+///     stackful::yield_now();
+///     1
+/// });
+/// let b = executor.spawn(|| 2);
+/// assert_eq!(executor.block_on(async { a.await + b.await }), 3);
+/// ```
+pub struct LocalExecutor {
+    inner: Rc<Inner>,
+    // Make sure the executor is not Send.
+    _marker: PhantomData<*const ()>,
+}
+
+impl Default for LocalExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LocalExecutor {
+    pub fn new() -> Self {
+        Self {
+            inner: Rc::new(Inner {
+                tasks: RefCell::new(Vec::new()),
+                free: RefCell::new(Vec::new()),
+                queue: Arc::new(Queue {
+                    ready: Mutex::new(VecDeque::new()),
+                    condvar: Condvar::new(),
+                }),
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Spawn a task running `f` on a new fiber.
+    ///
+    /// The task only makes progress while `run_until_stalled` or `block_on` is running.
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + 'static,
+        T: 'static,
+    {
+        self.inner.spawn(f)
+    }
+
+    /// Run tasks until none of them can make progress without waiting.
+    pub fn run_until_stalled(&self) {
+        let _enter = Enter::new(&self.inner);
+        let mut main_woken = false;
+        while let Some(id) = self.inner.queue.pop() {
+            if id == MAIN {
+                main_woken = true;
+            } else {
+                self.inner.poll_task(id);
+            }
+        }
+        // Don't lose the wakeup if we are called from the future passed to `block_on`.
+        if main_woken {
+            self.inner.queue.push(MAIN);
+        }
+    }
+
+    /// Run tasks until `fut` completes, blocking the thread when all of them are waiting.
+    pub fn block_on<F: Future>(&self, fut: F) -> F::Output {
+        let _enter = Enter::new(&self.inner);
+        let mut fut = std::pin::pin!(fut);
+        let main = Arc::new(TaskWaker {
+            id: MAIN,
+            queue: self.inner.queue.clone(),
+            queued: AtomicBool::new(false),
+        });
+        let waker = Waker::from(main.clone());
+        main.wake_by_ref();
+        loop {
+            while let Some(id) = self.inner.queue.pop() {
+                if id != MAIN {
+                    self.inner.poll_task(id);
+                    continue;
+                }
+                main.queued.store(false, Ordering::Release);
+                if let Poll::Ready(val) = fut.as_mut().poll(&mut Context::from_waker(&waker)) {
+                    return val;
+                }
+            }
+            self.inner.queue.park();
+        }
+    }
+}
+
+/// Spawn a task on the `LocalExecutor` that is currently running.
+///
+/// Panics if called outside of `LocalExecutor::run_until_stalled` or `LocalExecutor::block_on`.
+pub fn spawn_local<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + 'static,
+    T: 'static,
+{
+    let inner = CURRENT.with(|c| c.borrow().clone());
+    inner
+        .expect("`spawn_local` called outside of a `LocalExecutor`")
+        .spawn(f)
+}
+
+#[test]
+fn test_local_executor() {
+    use crate::yield_now;
+    use std::cell::Cell;
+    use std::time::Duration;
+
+    let executor = LocalExecutor::new();
+    let log = Rc::new(RefCell::new(Vec::new()));
+    for name in ["a", "b"] {
+        let log = log.clone();
+        executor.spawn(move || {
+            for i in 0..2 {
+                log.borrow_mut().push((name, i));
+                yield_now();
+            }
+        });
+    }
+    executor.run_until_stalled();
+    assert_eq!(*log.borrow(), [("a", 0), ("b", 0), ("a", 1), ("b", 1)]);
+
+    let done = Rc::new(Cell::new(false));
+    let task_done = done.clone();
+    let handle = executor.spawn(move || {
+        let inner = spawn_local(|| {
+            // Woken from the timer thread.
+            crate::wait_timeout(core::future::pending::<()>(), Duration::from_millis(50))
+                .unwrap_err();
+            2
+        });
+        let val = inner.join();
+        task_done.set(true);
+        val * 2
+    });
+    assert_eq!(executor.block_on(handle), 4);
+    assert!(done.get());
+}
+
+#[test]
+#[should_panic(expected = "task panic")]
+fn test_local_executor_panic() {
+    let executor = LocalExecutor::new();
+    let handle = executor.spawn(|| panic!("task panic"));
+    executor.block_on(handle);
+}
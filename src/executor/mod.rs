@@ -0,0 +1,86 @@
+//! Executors that run stackful tasks without depending on an async runtime.
+
+mod local;
+mod pool;
+
+pub use local::{spawn_local, LocalExecutor};
+pub use pool::ThreadPool;
+
+use crate::future::{wait, StackfulFuture};
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+struct JoinState<T> {
+    result: Option<std::thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// Handle to wait for a task spawned on an executor.
+///
+/// Awaiting the handle resumes the panic if the task panicked. Dropping it detaches the task.
+pub struct JoinHandle<T> {
+    state: Arc<Mutex<JoinState<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Wait for the task to complete from sync code.
+    ///
+    /// For a `LocalExecutor`, this must be called from another task of the same executor, as the
+    /// task can't make progress otherwise.
+    pub fn join(self) -> T {
+        wait(self)
+    }
+
+    /// Check if the task has finished running.
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().unwrap().result.is_some()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result.unwrap_or_else(|p| std::panic::resume_unwind(p))),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Create the future driving a task running `f`, and the handle to wait for its result.
+fn task<'a, F, T>(f: F) -> (impl Future<Output = ()> + 'a, JoinHandle<T>)
+where
+    F: FnOnce() -> T + 'a,
+    T: 'a,
+{
+    let state = Arc::new(Mutex::new(JoinState {
+        result: None,
+        waker: None,
+    }));
+    let task_state = state.clone();
+    let mut fiber = StackfulFuture::new(f).catch_unwind();
+    let fut = core::future::poll_fn(move |cx| {
+        let result = match Pin::new(&mut fiber).poll(cx) {
+            Poll::Ready(v) => v,
+            Poll::Pending => return Poll::Pending,
+        };
+        let waker = {
+            let mut state = task_state.lock().unwrap();
+            state.result = Some(result);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Poll::Ready(())
+    });
+    (fut, JoinHandle { state })
+}
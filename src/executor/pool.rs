@@ -0,0 +1,343 @@
+use super::{task, JoinHandle};
+
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::task::{Context, Wake, Waker};
+use std::thread::{self, Thread};
+
+// Value of `Task::home` before the task is first polled.
+const UNSTARTED: usize = usize::MAX;
+
+struct Task {
+    // `None` once the task has completed.
+    fut: Mutex<Option<Pin<Box<dyn Future<Output = ()>>>>>,
+    // Worker the task is bound to once started. A running fiber never changes thread.
+    home: AtomicUsize,
+    // Avoid queueing a task multiple times if it's woken repeatedly before being polled.
+    scheduled: AtomicBool,
+    shared: Weak<Shared>,
+}
+
+// SAFETY: Before the first poll, the future only contains a `Send` function and an unused stack.
+// After that it's only ever polled or dropped by its home worker.
+unsafe impl Send for Task {}
+unsafe impl Sync for Task {}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        if let Some(shared) = self.shared.upgrade() {
+            shared.schedule(self.clone());
+        }
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        let fut = self.fut.get_mut().unwrap().take();
+        let home = *self.home.get_mut();
+        if fut.is_some() && home != UNSTARTED && current_worker(&self.shared) != Some(home) {
+            // Unwinding the fiber away from its home thread is not allowed, so leak it instead.
+            std::mem::forget(fut);
+        }
+    }
+}
+
+struct Worker {
+    // Tasks ready to run on this worker. Unstarted tasks may be stolen by other workers.
+    local: Mutex<VecDeque<Arc<Task>>>,
+    // Started tasks that haven't completed, keyed by address. The worker keeps them alive even if
+    // nothing else holds their waker, and drops them itself when the pool shuts down.
+    owned: Mutex<HashMap<usize, Arc<Task>>>,
+    thread: OnceLock<Thread>,
+}
+
+struct Shared {
+    injector: Mutex<VecDeque<Arc<Task>>>,
+    workers: Vec<Worker>,
+    idle: Mutex<Vec<usize>>,
+    shutdown: AtomicBool,
+}
+
+thread_local! {
+    // The pool and index of the worker running on this thread.
+    static WORKER: Cell<(*const Shared, usize)> = const { Cell::new((std::ptr::null(), 0)) };
+}
+
+fn current_worker(shared: &Weak<Shared>) -> Option<usize> {
+    let (ptr, index) = WORKER.with(Cell::get);
+    if ptr == shared.as_ptr() {
+        Some(index)
+    } else {
+        None
+    }
+}
+
+impl Shared {
+    fn schedule(self: &Arc<Self>, task: Arc<Task>) {
+        let home = task.home.load(Ordering::Acquire);
+        if home != UNSTARTED {
+            self.workers[home].local.lock().unwrap().push_back(task);
+            self.unpark(home);
+            return;
+        }
+        match current_worker(&Arc::downgrade(self)) {
+            Some(index) => self.workers[index].local.lock().unwrap().push_back(task),
+            None => self.injector.lock().unwrap().push_back(task),
+        }
+        // Let an idle worker pick up or steal the new task.
+        let idle = self.idle.lock().unwrap().pop();
+        if let Some(index) = idle {
+            self.unpark(index);
+        }
+    }
+
+    fn unpark(&self, index: usize) {
+        if let Some(thread) = self.workers[index].thread.get() {
+            thread.unpark();
+        }
+    }
+
+    fn find_task(&self, index: usize) -> Option<Arc<Task>> {
+        if let Some(task) = self.workers[index].local.lock().unwrap().pop_front() {
+            return Some(task);
+        }
+        if let Some(task) = self.injector.lock().unwrap().pop_front() {
+            return Some(task);
+        }
+        // Steal from the back of other workers' queues, starting from our neighbour.
+        let count = self.workers.len();
+        for other in (1..count).map(|i| (index + i) % count) {
+            let mut local = self.workers[other].local.lock().unwrap();
+            let pos = local
+                .iter()
+                .rposition(|t| t.home.load(Ordering::Acquire) == UNSTARTED);
+            if let Some(pos) = pos {
+                return local.remove(pos);
+            }
+        }
+        None
+    }
+
+    fn run(&self, task: Arc<Task>, index: usize) {
+        task.scheduled.store(false, Ordering::Release);
+        let mut fut = task.fut.lock().unwrap();
+        let pending = match fut.as_mut() {
+            Some(fut) => {
+                if task.home.load(Ordering::Acquire) == UNSTARTED {
+                    task.home.store(index, Ordering::Release);
+                    self.workers[index]
+                        .owned
+                        .lock()
+                        .unwrap()
+                        .insert(Arc::as_ptr(&task) as usize, task.clone());
+                }
+                let waker = Waker::from(task.clone());
+                fut.as_mut()
+                    .poll(&mut Context::from_waker(&waker))
+                    .is_pending()
+            }
+            // Woken after completion.
+            None => return,
+        };
+        if !pending {
+            *fut = None;
+            drop(fut);
+            self.workers[index]
+                .owned
+                .lock()
+                .unwrap()
+                .remove(&(Arc::as_ptr(&task) as usize));
+        }
+    }
+
+    fn work(&self, index: usize) {
+        while !self.shutdown.load(Ordering::Acquire) {
+            if let Some(task) = self.find_task(index) {
+                self.run(task, index);
+                continue;
+            }
+            self.idle.lock().unwrap().push(index);
+            // Check again, in case a task was scheduled before we registered as idle.
+            if let Some(task) = self.find_task(index) {
+                self.idle.lock().unwrap().retain(|&i| i != index);
+                self.run(task, index);
+                continue;
+            }
+            thread::park();
+            self.idle.lock().unwrap().retain(|&i| i != index);
+        }
+
+        // Drop tasks that are still running, as this is the only thread that can unwind them.
+        // Their destructors may wake other tasks, so don't hold any lock while dropping them.
+        let queued = std::mem::take(&mut *self.workers[index].local.lock().unwrap());
+        drop(queued);
+        let owned = std::mem::take(&mut *self.workers[index].owned.lock().unwrap());
+        for task in owned.values() {
+            let fut = task.fut.lock().unwrap().take();
+            drop(fut);
+        }
+    }
+}
+
+/// A multi-threaded executor for stackful tasks.
+///
+/// Tasks are spread across worker threads, and idle workers steal tasks that haven't started
+/// yet from busy ones. Once a task starts running, its fiber stays on the same worker, so the
+/// function doesn't need to worry about moving between threads in the middle of a `wait`.
+///
+/// Dropping the pool cancels all tasks that haven't completed.
+///
+/// ```
+/// use stackful::executor::ThreadPool;
+///
+/// let pool = ThreadPool::new(4);
+/// let handles: Vec<_> = (0..8).map(|i| pool.spawn(move || i * 2)).collect();
+/// let sum: i32 = handles.into_iter().map(|h| h.join()).sum();
+/// assert_eq!(sum, 56);
+/// ```
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl Default for ThreadPool {
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
+
+impl ThreadPool {
+    /// Create a pool with the given number of worker threads.
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "a thread pool needs at least one thread");
+        let shared = Arc::new(Shared {
+            injector: Mutex::new(VecDeque::new()),
+            workers: (0..threads)
+                .map(|_| Worker {
+                    local: Mutex::new(VecDeque::new()),
+                    owned: Mutex::new(HashMap::new()),
+                    thread: OnceLock::new(),
+                })
+                .collect(),
+            idle: Mutex::new(Vec::new()),
+            shutdown: AtomicBool::new(false),
+        });
+        let threads = (0..threads)
+            .map(|index| {
+                let shared = shared.clone();
+                thread::Builder::new()
+                    .name(format!("stackful-worker-{}", index))
+                    .spawn(move || {
+                        shared.workers[index].thread.get_or_init(thread::current);
+                        WORKER.with(|w| w.set((Arc::as_ptr(&shared), index)));
+                        // Workers run many short fibers, so reuse their stacks.
+                        crate::fiber::enable_stack_pool();
+                        shared.work(index);
+                    })
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        Self { shared, threads }
+    }
+
+    /// Spawn a task running `f` on a new fiber.
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (fut, handle) = task(f);
+        let task = Arc::new(Task {
+            fut: Mutex::new(Some(Box::pin(fut))),
+            home: AtomicUsize::new(UNSTARTED),
+            scheduled: AtomicBool::new(false),
+            shared: Arc::downgrade(&self.shared),
+        });
+        task.wake_by_ref();
+        handle
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Release);
+        for index in 0..self.threads.len() {
+            self.shared.unpark(index);
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+        let queued = std::mem::take(&mut *self.shared.injector.lock().unwrap());
+        drop(queued);
+    }
+}
+
+#[test]
+fn test_thread_pool() {
+    use crate::yield_now;
+    use std::time::Duration;
+
+    let pool = ThreadPool::new(4);
+    let handles: Vec<_> = (0..32)
+        .map(|i| {
+            pool.spawn(move || {
+                let thread = thread::current().id();
+                for _ in 0..4 {
+                    yield_now();
+                    crate::wait_timeout(core::future::pending::<()>(), Duration::from_millis(1))
+                        .unwrap_err();
+                    // Started tasks never move to another worker.
+                    assert_eq!(thread::current().id(), thread);
+                }
+                i
+            })
+        })
+        .collect();
+    let sum: usize = handles.into_iter().map(JoinHandle::join).sum();
+    assert_eq!(sum, (0..32).sum());
+}
+
+#[test]
+fn test_thread_pool_cancel() {
+    use std::sync::atomic::AtomicUsize;
+
+    static STARTED: AtomicUsize = AtomicUsize::new(0);
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    struct Guard;
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let pool = ThreadPool::new(2);
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            pool.spawn(|| {
+                let _guard = Guard;
+                STARTED.fetch_add(1, Ordering::Relaxed);
+                crate::wait(core::future::pending::<()>());
+            })
+        })
+        .collect();
+    while STARTED.load(Ordering::Relaxed) != 4 {
+        thread::yield_now();
+    }
+    // Nothing holds the wakers of the tasks, but the pool keeps them alive.
+    thread::sleep(std::time::Duration::from_millis(10));
+    assert_eq!(DROPPED.load(Ordering::Relaxed), 0);
+    drop(pool);
+    assert_eq!(DROPPED.load(Ordering::Relaxed), 4);
+    assert!(handles.iter().all(|h| !h.is_finished()));
+}
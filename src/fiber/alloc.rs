@@ -2,6 +2,9 @@ use super::*;
 
 pub struct Stack(usize);
 
+/// Stacks are not pooled on this platform.
+pub fn enable_stack_pool() {}
+
 impl Stack {
     pub fn allocate() -> Self {
         Self(unsafe {
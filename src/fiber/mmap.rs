@@ -1,6 +1,7 @@
 use super::*;
 use crate::page_size;
 
+use std::cell::RefCell;
 use std::ptr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
@@ -9,10 +10,47 @@ pub struct Stack(usize);
 // Keep a stack so that repeated fiber calls don't require new allocation.
 static STACK_CACHE: AtomicUsize = AtomicUsize::new(0);
 
+// Maximum number of stacks kept by each thread.
+const POOL_SIZE: usize = 8;
+
+// Per-thread stacks, so threads running many fibers (e.g. executor workers) don't contend on
+// `STACK_CACHE` or fall back to `mmap`. Only enabled on threads that ask for it, as the stacks
+// are kept until the thread exits.
+struct StackPool {
+    enabled: bool,
+    stacks: Vec<usize>,
+}
+
+impl Drop for StackPool {
+    fn drop(&mut self) {
+        for &stack in &self.stacks {
+            unsafe { libc::munmap(stack as _, 0x200000) };
+        }
+    }
+}
+
+thread_local! {
+    static STACK_POOL: RefCell<StackPool> = const {
+        RefCell::new(StackPool {
+            enabled: false,
+            stacks: Vec::new(),
+        })
+    };
+}
+
+/// Keep freed stacks on the current thread for reuse by fibers later created on it.
+pub fn enable_stack_pool() {
+    STACK_POOL.with(|pool| pool.borrow_mut().enabled = true);
+}
+
 impl Stack {
     pub fn allocate() -> Self {
-        // Before allocating, first check the cache.
-        let stack = STACK_CACHE.swap(0, Ordering::Relaxed);
+        // Before allocating, first check the caches.
+        let stack = STACK_POOL
+            .try_with(|pool| pool.borrow_mut().stacks.pop())
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| STACK_CACHE.swap(0, Ordering::Relaxed));
         if stack != 0 {
             return Self(stack);
         }
@@ -59,7 +97,19 @@ impl Stack {
 
 impl Drop for Stack {
     fn drop(&mut self) {
-        // Before freeing, first check the cache.
+        // Before freeing, first check the caches.
+        let pooled = STACK_POOL.try_with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.enabled && pool.stacks.len() < POOL_SIZE {
+                pool.stacks.push(self.0);
+                true
+            } else {
+                false
+            }
+        });
+        if pooled == Ok(true) {
+            return;
+        }
         if STACK_CACHE
             .compare_exchange(0, self.0, Ordering::Relaxed, Ordering::Relaxed)
             .is_ok()
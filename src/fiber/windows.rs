@@ -7,6 +7,9 @@ extern "C" {
     fn fiber_destroy(fiber: usize);
 }
 
+/// Stacks are not pooled on this platform.
+pub fn enable_stack_pool() {}
+
 impl Stack {
     pub fn allocate() -> Self {
         Self(unsafe { fiber_create() })
@@ -1,34 +1,206 @@
 use crate::generator::*;
+use crate::timer::{Elapsed, Timeout};
 
 use std::cell::Cell;
+use std::fmt;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::RwLock;
 use std::task::Poll;
+use std::time::{Duration, Instant};
 
 struct Context {
     parent: Cell<Option<&'static Context>>,
     yielder: Cell<Option<&'static YieldHandle<(), &'static Context>>>,
     panicking: Cell<bool>,
     ctx: *mut core::task::Context<'static>,
+    budget: Remaining,
+    #[cfg(feature = "tracing")]
+    outer: crate::trace::Outer,
 }
 
 thread_local! {
     static CONTEXT: Cell<Option<&'static Context>> = Cell::new(None);
 }
 
+// `CONTEXT` is only accessed through these functions. They are never inlined, so the address of
+// the thread-local can't be cached across a `wait`, after which we may be on a different thread
+// (see `stackful_send`).
+#[inline(never)]
+fn current_context() -> Option<&'static Context> {
+    CONTEXT.with(|ctx| ctx.get())
+}
+
+#[inline(never)]
+fn replace_context(context: Option<&'static Context>) -> Option<&'static Context> {
+    CONTEXT.with(|ctx| ctx.replace(context))
+}
+
+/// What `wait` does when it is not called from a fiber.
+///
+/// Variants depend on the enabled features, so matching on this needs a wildcard arm.
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub enum Fallback {
+    /// Block the current thread with `futures_executor::block_on`. This is the default.
+    BlockOn,
+    /// Panic.
+    Panic,
+    /// Use `tokio::task::block_in_place` and `Handle::block_on` if called inside a tokio
+    /// runtime, and `BlockOn` otherwise.
+    #[cfg(feature = "tokio")]
+    Tokio,
+    /// Run the future to completion with a user-supplied executor.
+    Custom(fn(Pin<&mut dyn Future<Output = ()>>)),
+}
+
+static FALLBACK: RwLock<Fallback> = RwLock::new(Fallback::BlockOn);
+
+/// Set what `wait` does when it is not called from a fiber.
+pub fn set_fallback(fallback: Fallback) {
+    *FALLBACK.write().unwrap() = fallback;
+}
+
+impl Fallback {
+    fn block_on<T>(self, fut: impl Future<Output = T>) -> T {
+        match self {
+            Fallback::BlockOn => futures_executor::block_on(fut),
+            Fallback::Panic => panic!("`wait` called outside of `stackful`"),
+            #[cfg(feature = "tokio")]
+            Fallback::Tokio => match tokio::runtime::Handle::try_current() {
+                Ok(handle) => {
+                    if let tokio::runtime::RuntimeFlavor::CurrentThread = handle.runtime_flavor() {
+                        panic!(
+                            "`wait` called outside of `stackful` on a current-thread tokio runtime"
+                        );
+                    }
+                    tokio::task::block_in_place(|| handle.block_on(fut))
+                }
+                Err(_) => futures_executor::block_on(fut),
+            },
+            Fallback::Custom(run) => {
+                let mut output = None;
+                let mut fut = async {
+                    output = Some(fut.await);
+                };
+                // SAFETY: This is safe because we don't move fut.
+                run(unsafe { Pin::new_unchecked(&mut fut) });
+                drop(fut);
+                output.expect("custom fallback returned before the future completed")
+            }
+        }
+    }
+}
+
 /// Wait for a future to complete and return its output.
 ///
 /// If the function is called directly or recursively from a closure passed to `stackful`,
 /// then the `Future` returned by `stackful` would return `Pending`. Otherwise the current
-/// thread would block until the future has been completed.
-pub fn wait<T>(mut fut: impl Future<Output = T>) -> T {
-    let mut context = match CONTEXT.with(|ctx| ctx.get()) {
-        Some(v) => v,
+/// thread would block until the future has been completed, or whatever `set_fallback`
+/// specifies.
+pub fn wait<T>(fut: impl Future<Output = T>) -> T {
+    match current_context() {
+        Some(context) => wait_in(context, fut),
         None => {
-            // Not called from a fiber context, do a block_on instead.
-            return futures_executor::block_on(fut);
+            let fallback = *FALLBACK.read().unwrap();
+            fallback.block_on(fut)
+        }
+    }
+}
+
+/// Same as `wait`, but use the given fallback instead of the global one if not called from a
+/// fiber.
+pub fn wait_with<T>(fut: impl Future<Output = T>, fallback: Fallback) -> T {
+    match current_context() {
+        Some(context) => wait_in(context, fut),
+        None => fallback.block_on(fut),
+    }
+}
+
+/// Wait for a future to complete, giving up after `timeout`.
+///
+/// The future is dropped if it doesn't complete in time. This works both inside and outside of a
+/// fiber, and doesn't depend on any particular runtime.
+pub fn wait_timeout<T>(fut: impl Future<Output = T>, timeout: Duration) -> Result<T, Elapsed> {
+    wait_deadline(fut, Instant::now() + timeout)
+}
+
+/// Wait for a future to complete, giving up at `deadline`.
+///
+/// See `wait_timeout` for details.
+pub fn wait_deadline<T>(fut: impl Future<Output = T>, deadline: Instant) -> Result<T, Elapsed> {
+    wait(Timeout::new(fut, deadline))
+}
+
+/// Check if called from a fiber, i.e. `wait` would suspend instead of using the fallback.
+pub fn in_fiber() -> bool {
+    current_context().is_some()
+}
+
+/// Error returned by `try_wait` when not called from a fiber.
+pub struct NotInFiber<F>(F);
+
+impl<F> NotInFiber<F> {
+    /// Get back the future passed to `try_wait`.
+    pub fn into_inner(self) -> F {
+        self.0
+    }
+}
+
+impl<F> fmt::Debug for NotInFiber<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotInFiber").finish()
+    }
+}
+
+impl<F> fmt::Display for NotInFiber<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("not called from a fiber")
+    }
+}
+
+impl<F> std::error::Error for NotInFiber<F> {}
+
+/// Same as `wait`, but return the future back instead of blocking if not called from a fiber.
+pub fn try_wait<F: Future>(fut: F) -> Result<F::Output, NotInFiber<F>> {
+    match current_context() {
+        Some(context) => Ok(wait_in(context, fut)),
+        None => Err(NotInFiber(fut)),
+    }
+}
+
+/// Wait until `f` returns `Ready`, calling it with the current `Context` each time we are woken.
+///
+/// This is useful to drive `poll_*` methods directly from sync code.
+pub fn wait_poll<T>(f: impl FnMut(&mut core::task::Context<'_>) -> Poll<T>) -> T {
+    wait(core::future::poll_fn(f))
+}
+
+/// Call `f` with the `Context` of the current poll, or return `None` if not called from a fiber.
+///
+/// `f` is not considered to be running in the fiber, so `wait` inside it blocks according to the
+/// fallback instead of suspending, and a nested `with_context` returns `None`.
+pub fn with_context<R>(f: impl FnOnce(&mut core::task::Context<'_>) -> R) -> Option<R> {
+    struct Restore(&'static Context);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            replace_context(Some(self.0));
         }
-    };
+    }
+
+    let context = current_context()?;
+    // Suspending or handing out the context again while `f` holds it would alias it, or leave it
+    // dangling once the poll ends.
+    replace_context(None);
+    let _restore = Restore(context);
+    Some(f(unsafe { &mut *context.ctx }))
+}
+
+fn wait_in<T>(mut context: &'static Context, mut fut: impl Future<Output = T>) -> T {
+    if context.budget.exhausted() {
+        unsafe { &*context.ctx }.waker().wake_by_ref();
+        context = suspend(context);
+    }
     loop {
         // SAFETY: This is safe because we don't move fut.
         if let Poll::Ready(val) = unsafe { Pin::new_unchecked(&mut fut) }
@@ -37,37 +209,102 @@ pub fn wait<T>(mut fut: impl Future<Output = T>) -> T {
         {
             return val;
         }
+        context = suspend(context);
+    }
+}
+
+// Yield `Pending` from the current `StackfulFuture`, and return the context of the next poll.
+fn suspend(context: &'static Context) -> &'static Context {
+    replace_context(context.parent.take());
+    let yielder = context.yielder.get().unwrap();
 
-        CONTEXT.with(|ctx| ctx.set(context.parent.take()));
-        let yielder = context.yielder.get().unwrap();
-
-        struct PanicGuard;
-        impl Drop for PanicGuard {
-            fn drop(&mut self) {
-                CONTEXT.with(|ctx| {
-                    let context = match ctx.get() {
-                        Some(v) => v,
-                        None => return,
-                    };
-                    context.panicking.set(true)
-                });
+    struct PanicGuard;
+    impl Drop for PanicGuard {
+        fn drop(&mut self) {
+            if let Some(context) = current_context() {
+                context.panicking.set(true)
             }
         }
+    }
+
+    let guard = PanicGuard;
+    // Don't leave spans entered inside the fiber on the thread while we are suspended.
+    #[cfg(feature = "tracing")]
+    let suspended = crate::trace::Suspended::leave(&context.outer);
+    let context = yielder.yeet(());
+    #[cfg(feature = "tracing")]
+    drop(suspended);
+    core::mem::forget(guard);
+
+    context.parent.set(replace_context(Some(context)));
+    context.yielder.set(Some(yielder));
+    context
+}
+
+/// Yield to the executor once, letting other tasks run.
+///
+/// Outside of a fiber this yields the current thread instead.
+pub fn yield_now() {
+    match current_context() {
+        Some(context) => {
+            unsafe { &*context.ctx }.waker().wake_by_ref();
+            suspend(context);
+        }
+        None => std::thread::yield_now(),
+    }
+}
+
+/// Limit on how long a `StackfulFuture` may run in a single poll.
+///
+/// Once exhausted, the next `wait` yields to the executor before doing anything else, even if
+/// the future it waits for is ready.
+#[derive(Clone, Copy, Debug)]
+pub enum Budget {
+    /// Number of `wait` calls allowed in a single poll.
+    Waits(u32),
+    /// Time allowed to elapse in a single poll.
+    Time(Duration),
+}
 
-        let guard = PanicGuard;
-        context = yielder.yeet(());
-        core::mem::forget(guard);
+enum Remaining {
+    Unlimited,
+    Waits(Cell<u32>),
+    Until(Instant),
+}
+
+impl Remaining {
+    fn new(budget: Option<Budget>) -> Self {
+        match budget {
+            None => Remaining::Unlimited,
+            Some(Budget::Waits(n)) => Remaining::Waits(Cell::new(n)),
+            Some(Budget::Time(dur)) => Remaining::Until(Instant::now() + dur),
+        }
+    }
 
-        CONTEXT.with(|ctx| {
-            context.parent.set(ctx.take());
-            context.yielder.set(Some(yielder));
-            ctx.set(Some(context));
-        });
+    // Consume budget for a `wait` call, and check whether there was none left.
+    fn exhausted(&self) -> bool {
+        match self {
+            Remaining::Unlimited => false,
+            Remaining::Waits(n) => match n.get() {
+                0 => true,
+                v => {
+                    n.set(v - 1);
+                    false
+                }
+            },
+            Remaining::Until(deadline) => Instant::now() >= *deadline,
+        }
     }
 }
 
 pub struct StackfulFuture<'a, T> {
     generator: StackfulGenerator<'a, (), T, &'static Context>,
+    budget: Option<Budget>,
+    #[cfg(feature = "tracing")]
+    task: Option<crate::trace::TaskSpan>,
+    // Default dispatcher of the fiber while it is suspended.
+    #[cfg(feature = "tracing")]
+    dispatch: Option<tracing::Dispatch>,
 }
 
 impl<'a, T> StackfulFuture<'a, T> {
@@ -78,26 +315,20 @@ impl<'a, T> StackfulFuture<'a, T> {
         Self {
             generator: StackfulGenerator::new(
                 move |y: &YieldHandle<(), &'static Context>, context: &'static Context| {
-                    CONTEXT.with(|ctx| {
-                        context.parent.set(ctx.take());
-                        context.yielder.set(Some(unsafe { std::mem::transmute(y) }));
-                        ctx.set(Some(context));
-                    });
+                    context.parent.set(replace_context(Some(context)));
+                    context.yielder.set(Some(unsafe { std::mem::transmute(y) }));
 
                     struct ScopeGuard;
                     impl Drop for ScopeGuard {
                         fn drop(&mut self) {
-                            CONTEXT.with(|ctx| {
-                                let context = match ctx.get() {
-                                    Some(v) => v,
-                                    None => return,
-                                };
-                                if context.panicking.get() {
-                                    return;
-                                }
-                                let parent = context.parent.take();
-                                ctx.set(parent);
-                            });
+                            let context = match current_context() {
+                                Some(v) => v,
+                                None => return,
+                            };
+                            if context.panicking.get() {
+                                return;
+                            }
+                            replace_context(context.parent.take());
                         }
                     }
 
@@ -105,23 +336,103 @@ impl<'a, T> StackfulFuture<'a, T> {
                     f()
                 },
             ),
+            budget: None,
+            #[cfg(feature = "tracing")]
+            task: None,
+            #[cfg(feature = "tracing")]
+            dispatch: None,
         }
     }
 }
 
-impl<T> Future for StackfulFuture<'_, T> {
-    type Output = T;
+impl<'a, T> StackfulFuture<'a, T> {
+    /// Catch panics from the function, resolving to `Err` with the panic payload instead.
+    pub fn catch_unwind(self) -> CatchUnwind<'a, T> {
+        CatchUnwind(self)
+    }
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<T> {
+    /// Make `wait` yield to the executor when the budget of a poll is exhausted.
+    ///
+    /// This stops sync code that calls `wait` often but rarely gets `Pending` from starving other
+    /// tasks.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Run the function inside `span`, entering it on every poll.
+    ///
+    /// If the span has `polls` or `suspended_us` fields, they are recorded with the number of
+    /// polls so far and the total time in microseconds the task has been suspended.
+    #[cfg(feature = "tracing")]
+    pub fn instrument(mut self, span: tracing::Span) -> Self {
+        self.task = Some(crate::trace::TaskSpan::new(span));
+        self
+    }
+
+    fn try_poll(&mut self, cx: &mut core::task::Context<'_>) -> Result<Poll<T>, PanicPayload> {
+        #[cfg(feature = "tracing")]
+        let entered = self.task.as_mut().map(crate::trace::TaskSpan::enter);
+        #[cfg(feature = "tracing")]
+        let state = {
+            let (state, dispatch) =
+                crate::trace::with_dispatch(self.dispatch.take(), || self.resume(cx));
+            self.dispatch = Some(dispatch);
+            state
+        };
+        #[cfg(not(feature = "tracing"))]
+        let state = self.resume(cx);
+        let result = match state? {
+            GeneratorState::Yielded(()) => Poll::Pending,
+            GeneratorState::Complete(val) => Poll::Ready(val),
+        };
+        #[cfg(feature = "tracing")]
+        {
+            drop(entered);
+            if let Some(task) = &mut self.task {
+                task.exit(result.is_pending());
+            }
+        }
+        Ok(result)
+    }
+
+    fn resume(
+        &mut self,
+        cx: &mut core::task::Context<'_>,
+    ) -> Result<GeneratorState<(), T>, PanicPayload> {
         let ctx = Context {
             parent: Cell::new(None),
             yielder: Cell::new(None),
             panicking: Cell::new(false),
             ctx: unsafe { std::mem::transmute(cx) },
+            budget: Remaining::new(self.budget),
+            #[cfg(feature = "tracing")]
+            outer: crate::trace::Outer::capture(),
         };
-        match Pin::new(&mut self.generator).resume(unsafe { std::mem::transmute(&ctx) }) {
-            GeneratorState::Yielded(()) => Poll::Pending,
-            GeneratorState::Complete(val) => Poll::Ready(val),
+        Pin::new(&mut self.generator).try_resume(unsafe { std::mem::transmute(&ctx) })
+    }
+}
+
+impl<T> Future for StackfulFuture<'_, T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<T> {
+        self.try_poll(cx)
+            .unwrap_or_else(|p| std::panic::resume_unwind(p))
+    }
+}
+
+/// Future returned by [`StackfulFuture::catch_unwind`].
+pub struct CatchUnwind<'a, T>(StackfulFuture<'a, T>);
+
+impl<T> Future for CatchUnwind<'_, T> {
+    type Output = Result<T, PanicPayload>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<Self::Output> {
+        match self.0.try_poll(cx) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(val)) => Poll::Ready(Ok(val)),
+            Err(p) => Poll::Ready(Err(p)),
         }
     }
 }
@@ -134,6 +445,65 @@ pub async fn stackful<T, F: FnOnce() -> T>(f: F) -> T {
     StackfulFuture::new(f).await
 }
 
+/// A `StackfulFuture` that can be sent to other threads, created by [`stackful_send`].
+pub struct SendStackfulFuture<'a, T>(StackfulFuture<'a, T>);
+
+// SAFETY: Upheld by the caller of `stackful_send`.
+unsafe impl<T: Send> Send for SendStackfulFuture<'_, T> {}
+
+impl<T> Future for SendStackfulFuture<'_, T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut core::task::Context<'_>) -> Poll<T> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+/// Turn a synchronous function into a `Future` that is `Send`, so it can be spawned on a
+/// multi-threaded executor and polled from different threads.
+///
+/// The fiber keeps its own stack when it moves, while the context used by `wait`, the stack limit
+/// and fiber-locals are rebound on every poll.
+///
+/// # Safety
+///
+/// Each poll may run on a different thread, so anything the function holds across a call to
+/// `wait` effectively moves between threads. The caller must make sure that:
+/// * no `!Send` value (e.g. an `Rc` or a `MutexGuard`) is held across a `wait`;
+/// * no reference into a thread-local (e.g. obtained from `LocalKey::with`) is held across a
+///   `wait`, as it would refer to the thread-local of the previous thread;
+/// * thread-locals whose state must stay with the fiber are made fiber-local, or registered with
+///   `register_switch_hook`;
+/// * values stored in fiber-locals are `Send`, as they move with the fiber.
+pub unsafe fn stackful_send<'a, T, F>(f: F) -> SendStackfulFuture<'a, T>
+where
+    T: Send,
+    F: FnOnce() -> T + Send + 'a,
+{
+    SendStackfulFuture(StackfulFuture::new(f))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+fn send_between_threads() {
+    let mut fut = Box::pin(unsafe {
+        stackful_send(|| {
+            let before = std::thread::current().id();
+            yield_now();
+            before != std::thread::current().id()
+        })
+    });
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = core::task::Context::from_waker(waker);
+    assert!(fut.as_mut().poll(&mut cx).is_pending());
+    let moved = std::thread::spawn(move || {
+        let waker = futures::task::noop_waker_ref();
+        let mut cx = core::task::Context::from_waker(waker);
+        fut.as_mut().poll(&mut cx)
+    });
+    assert_eq!(moved.join().unwrap(), Poll::Ready(true));
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 #[test]
 #[should_panic]
@@ -144,6 +514,23 @@ fn panick() {
     }));
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+fn catch_unwind() {
+    let result = async_std::task::block_on(
+        StackfulFuture::new(|| {
+            wait(async_std::task::yield_now());
+            panic!("plugin failure");
+        })
+        .catch_unwind(),
+    );
+    assert_eq!(
+        *result.unwrap_err().downcast::<&str>().unwrap(),
+        "plugin failure"
+    );
+    assert!(current_context().is_none());
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 #[test]
 fn drop_before_polling() {
@@ -162,7 +549,189 @@ fn drop_after_polling() {
     }));
     let _ = Pin::new(&mut fut).poll(&mut cx);
     drop(fut);
-    assert!(CONTEXT.with(|ctx| ctx.get()).is_none());
+    assert!(current_context().is_none());
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_span() {
+    use tracing::subscriber::with_default;
+    use tracing::Span;
+
+    with_default(tracing_subscriber::registry(), || {
+        let waker = futures::task::noop_waker_ref();
+        let mut cx = core::task::Context::from_waker(waker);
+        let task = tracing::info_span!("task", polls = tracing::field::Empty);
+        let mut fut = StackfulFuture::new(|| {
+            let span = tracing::info_span!("inner");
+            let _entered = span.enter();
+            let mut yielded = false;
+            wait(futures::future::poll_fn(|cx| {
+                if yielded {
+                    return Poll::Ready(());
+                }
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }));
+            Span::current().id() == span.id()
+        })
+        .instrument(task);
+
+        let mut polled = false;
+        loop {
+            match Pin::new(&mut fut).poll(&mut cx) {
+                Poll::Ready(v) => break assert!(v),
+                Poll::Pending => {
+                    assert!(Span::current().is_none());
+                    polled = true;
+                }
+            }
+        }
+        assert!(polled);
+    });
+}
+
+#[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
+#[test]
+fn tracing_send_between_threads() {
+    use tracing::dispatcher;
+
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = core::task::Context::from_waker(waker);
+    let mut fut = Box::pin(unsafe {
+        stackful_send(|| {
+            yield_now();
+            // The dispatcher the fiber started with follows it to the new thread.
+            dispatcher::get_default(|d| d.is::<tracing_subscriber::Registry>())
+        })
+    });
+    tracing::subscriber::with_default(tracing_subscriber::registry(), || {
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+    });
+    let moved = std::thread::spawn(move || {
+        let waker = futures::task::noop_waker_ref();
+        let mut cx = core::task::Context::from_waker(waker);
+        fut.as_mut().poll(&mut cx)
+    });
+    assert_eq!(moved.join().unwrap(), Poll::Ready(true));
+}
+
+#[test]
+fn yield_and_budget() {
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = core::task::Context::from_waker(waker);
+
+    let mut fut = StackfulFuture::new(yield_now);
+    assert!(Pin::new(&mut fut).poll(&mut cx).is_pending());
+    assert!(Pin::new(&mut fut).poll(&mut cx).is_ready());
+
+    let mut fut = StackfulFuture::new(|| {
+        for _ in 0..5 {
+            wait(async {});
+        }
+    })
+    .with_budget(Budget::Waits(2));
+    assert!(Pin::new(&mut fut).poll(&mut cx).is_pending());
+    assert!(Pin::new(&mut fut).poll(&mut cx).is_ready());
+}
+
+#[test]
+fn poll_access() {
+    assert!(with_context(|_| ()).is_none());
+    let val = futures_executor::block_on(stackful(|| {
+        assert!(with_context(|_| ()).is_some());
+        let mut count = 0;
+        wait_poll(|cx| {
+            count += 1;
+            if count < 3 {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Poll::Ready(count)
+        })
+    }));
+    assert_eq!(val, 3);
+}
+
+#[test]
+fn wait_in_with_context() {
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = core::task::Context::from_waker(waker);
+    let mut fut = StackfulFuture::new(|| {
+        // Neither suspends nor hands out the context again while it is borrowed.
+        let nested = with_context(|_| (wait(async { 1 }), with_context(|_| ()).is_none()));
+        assert_eq!(nested, Some((1, true)));
+        yield_now();
+        with_context(|_| ()).is_some()
+    });
+    assert!(Pin::new(&mut fut).poll(&mut cx).is_pending());
+    assert_eq!(Pin::new(&mut fut).poll(&mut cx), Poll::Ready(true));
+}
+
+#[test]
+fn timeout() {
+    let timeout = Duration::from_millis(100);
+    assert_eq!(wait_timeout(async { 1 }, timeout), Ok(1));
+    assert!(wait_timeout(futures::future::pending::<()>(), timeout).is_err());
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+fn timeout_in_fiber() {
+    async_std::task::block_on(stackful(|| {
+        let sleep = |ms| async_std::task::sleep(Duration::from_millis(ms));
+        assert!(wait_timeout(sleep(1000), Duration::from_millis(100)).is_err());
+        assert!(wait_timeout(sleep(10), Duration::from_millis(1000)).is_ok());
+    }));
+}
+
+#[test]
+fn try_wait_outside() {
+    assert!(!in_fiber());
+    let fut = try_wait(async { 1 }).unwrap_err().into_inner();
+    assert_eq!(
+        futures_executor::block_on(stackful(|| {
+            assert!(in_fiber());
+            try_wait(fut).unwrap()
+        })),
+        1
+    );
+}
+
+#[test]
+#[should_panic(expected = "`wait` called outside of `stackful`")]
+fn fallback_panic() {
+    wait_with(async {}, Fallback::Panic);
+}
+
+#[test]
+fn fallback_custom() {
+    static CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    fn run(fut: Pin<&mut dyn Future<Output = ()>>) {
+        CALLS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        futures_executor::block_on(fut);
+    }
+
+    assert_eq!(wait_with(async { 1 }, Fallback::Custom(run)), 1);
+    assert_eq!(CALLS.load(std::sync::atomic::Ordering::Relaxed), 1);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn fallback_tokio() {
+    let rt = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+    let val = rt.block_on(async {
+        wait_with(
+            async {
+                tokio::task::yield_now().await;
+                1
+            },
+            Fallback::Tokio,
+        )
+    });
+    assert_eq!(val, 1);
 }
 
 #[cfg(not(target_arch = "wasm32"))]
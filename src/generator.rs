@@ -1,8 +1,11 @@
 use crate::fiber::*;
+use crate::local::LocalMap;
 
-use core::cell::Cell;
+use core::any::Any;
+use core::cell::{Cell, RefCell};
 use core::marker::PhantomData;
 use core::mem::ManuallyDrop;
+use core::ops::Deref;
 use core::pin::Pin;
 
 #[cfg(feature = "nightly")]
@@ -15,6 +18,56 @@ pub enum GeneratorState<Y, R> {
     Complete(R),
 }
 
+/// Result of `StackfulGenerator::resume_transfer`.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+pub enum TransferState<Y, R> {
+    /// The generator, or a peer it transferred control to, yielded a value.
+    Yielded(Y),
+    /// The generator returned a value.
+    Complete(R),
+    /// A peer that control was transferred to returned a value. The generator itself is still
+    /// suspended in `transfer`.
+    PeerComplete(R),
+}
+
+/// Where a `StackfulGenerator` is in its lifecycle.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum GeneratorStatus {
+    /// Not resumed yet.
+    NotStarted,
+    /// Suspended after yielding a value.
+    Suspended,
+    /// Currently executing.
+    Running,
+    /// Returned a value.
+    Completed,
+    /// Panicked, and is poisoned.
+    Panicked,
+}
+
+/// Error returned by `resume_checked` when the generator cannot be resumed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum ResumeError {
+    Running,
+    Completed,
+    Panicked,
+}
+
+impl core::fmt::Display for ResumeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            ResumeError::Running => "generator is already running",
+            ResumeError::Completed => "generator has already completed",
+            ResumeError::Panicked => "generator has panicked",
+        })
+    }
+}
+
+impl std::error::Error for ResumeError {}
+
+/// Payload of a panic caught by `try_resume`, as produced by `std::panic::catch_unwind`.
+pub type PanicPayload = Box<dyn Any + Send>;
+
 #[cfg(not(feature = "nightly"))]
 pub trait Generator<R = ()> {
     type Yield;
@@ -29,6 +82,9 @@ pub struct StackfulGenerator<'a, Y, R, Resume> {
     #[cfg(feature = "stacker")]
     stack_limit: Option<usize>,
     func: Option<Box<dyn FnOnce(&YieldHandle<Y, Resume>, Resume) -> R + 'a>>,
+    running: bool,
+    poisoned: bool,
+    locals: LocalMap,
     // Make sure this Generator is not Send.
     _marker: PhantomData<*const fn(Resume) -> (Y, R)>,
 }
@@ -41,6 +97,16 @@ pub struct YieldHandle<Y, Resume = ()> {
     _marker: PhantomData<(Y, Resume)>,
 }
 
+/// Handle given to generators created with `StackfulGenerator::new_symmetric`.
+///
+/// In addition to yielding through the `YieldHandle` it dereferences to, it can transfer control
+/// directly to another generator of the same type.
+#[repr(transparent)]
+pub struct TransferHandle<Y, R, Resume = ()> {
+    inner: YieldHandle<Y, Resume>,
+    _marker: PhantomData<fn() -> R>,
+}
+
 impl<'a, Y, R, Resume> StackfulGenerator<'a, Y, R, Resume> {
     pub fn new<F>(f: F) -> Self
     where
@@ -53,13 +119,40 @@ impl<'a, Y, R, Resume> StackfulGenerator<'a, Y, R, Resume> {
             #[cfg(feature = "stacker")]
             stack_limit: None,
             result: None,
+            running: false,
+            poisoned: false,
+            locals: LocalMap::default(),
             _marker: PhantomData,
         }
     }
+
+    /// Create a generator that can use `TransferHandle::transfer` to switch to its peers.
+    pub fn new_symmetric<F>(f: F) -> Self
+    where
+        F: FnOnce(&TransferHandle<Y, R, Resume>, Resume) -> R + 'a,
+    {
+        Self::new(move |y: &YieldHandle<Y, Resume>, r| {
+            // SAFETY: `TransferHandle` is a transparent wrapper of `YieldHandle`.
+            let y = unsafe {
+                &*(y as *const YieldHandle<Y, Resume> as *const TransferHandle<Y, R, Resume>)
+            };
+            f(y, r)
+        })
+    }
 }
 
 struct DropPanic;
 
+crate::fiber_local! {
+    // Set once the fiber starts unwinding with `DropPanic`.
+    static CANCELLING: Cell<bool> = Cell::new(false);
+}
+
+/// Whether the current fiber is unwinding because its generator was dropped before completing.
+pub(crate) fn cancelling() -> bool {
+    CANCELLING.with(Cell::get)
+}
+
 struct EnterPayload<'a, Y, R, Resume> {
     f: ManuallyDrop<Box<dyn FnOnce(&YieldHandle<Y, Resume>, Resume) -> R + 'a>>,
     p: usize,
@@ -68,7 +161,21 @@ struct EnterPayload<'a, Y, R, Resume> {
 enum YieldPayload {
     Yielded(*const ()),
     Complete(*const ()),
-    Panic(*mut (dyn std::any::Any + Send)),
+    Panic(*mut (dyn Any + Send)),
+    // Target generator and the argument to resume it with.
+    Transfer(*const (), *const ()),
+}
+
+enum Switched<Y, R, Resume> {
+    State(GeneratorState<Y, R>),
+    Transfer(*const (), Resume),
+}
+
+// Passed by reference when switching into a suspended generator. A null payload is used instead
+// when the generator is being dropped.
+enum ResumePayload<Resume> {
+    Resumed(Resume),
+    Panic(PanicPayload),
 }
 
 extern "C" fn enter<Y, R, Resume>(stack: StackPointer, payload: usize) -> ! {
@@ -98,45 +205,104 @@ impl<Y, R, Resume> Drop for StackfulGenerator<'_, Y, R, Resume> {
         if let Some(stack) = self.result {
             // This will give us a `YieldPayload::Panic(DropPanic)`, but we can safely ignore it
             // because DropPanic is a ZST.
+            let _locals = self.locals.enter();
             unsafe {
                 fiber_switch_enter(stack, 0);
             }
         }
+        let _locals = self.locals.enter();
+        self.locals.clear();
     }
 }
 
-impl<Y, R, Resume> Generator<Resume> for StackfulGenerator<'_, Y, R, Resume> {
-    type Yield = Y;
-    type Return = R;
+impl<Y, R, Resume> StackfulGenerator<'_, Y, R, Resume> {
+    fn resume_with(
+        &mut self,
+        arg: ResumePayload<Resume>,
+    ) -> Result<TransferState<Y, R>, PanicPayload> {
+        let mut switched = self.switch(arg)?;
+        let mut peer = false;
+        // If the generator transferred control to a peer, keep running on behalf of our caller
+        // until one of them yields or returns.
+        loop {
+            let (target, arg) = match switched {
+                Switched::State(GeneratorState::Yielded(y)) => {
+                    return Ok(TransferState::Yielded(y))
+                }
+                Switched::State(GeneratorState::Complete(r)) if peer => {
+                    return Ok(TransferState::PeerComplete(r))
+                }
+                Switched::State(GeneratorState::Complete(r)) => {
+                    return Ok(TransferState::Complete(r))
+                }
+                Switched::Transfer(target, arg) => (target, arg),
+            };
+            // SAFETY: `transfer` guarantees that the target has the same type as `self` except
+            // for the lifetime, which doesn't matter as we only resume it. The reference lives on
+            // the stack of the suspended generator, which stays valid until it is resumed.
+            let target = unsafe { &*(target as *const RefCell<Self>) };
+            peer = !core::ptr::eq(target.as_ptr(), self);
+            switched = if peer {
+                target
+                    .try_borrow_mut()
+                    .expect("transferring to a running generator")
+                    .switch(ResumePayload::Resumed(arg))?
+            } else {
+                self.switch(ResumePayload::Resumed(arg))?
+            };
+        }
+    }
 
-    fn resume(mut self: Pin<&mut Self>, arg: Resume) -> GeneratorState<Y, R> {
-        let payload = &arg as *const _ as usize;
+    fn switch(
+        &mut self,
+        arg: ResumePayload<Resume>,
+    ) -> Result<Switched<Y, R, Resume>, PanicPayload> {
+        assert!(!self.running, "resuming a running generator");
+        assert!(!self.poisoned, "resuming a poisoned generator");
         #[cfg(feature = "stacker")]
         let stack_limit = stacker::get_stack_limit();
+        // Keep the fiber-locals entered until the end, so they can be dropped on completion.
+        let _locals = self.locals.enter();
         let result = match self.result {
             None => {
+                let f = self.func.take().expect("polling a completed future");
+                let arg = match arg {
+                    ResumePayload::Resumed(v) => v,
+                    // The body hasn't started yet, so raise the panic right away.
+                    ResumePayload::Panic(p) => {
+                        self.poisoned = true;
+                        return Err(p);
+                    }
+                };
                 let mut payload = EnterPayload {
-                    f: ManuallyDrop::new(self.func.take().expect("polling a completed future")),
-                    p: payload,
+                    f: ManuallyDrop::new(f),
+                    p: &arg as *const _ as usize,
                 };
                 #[cfg(feature = "stacker")]
                 stacker::set_stack_limit(Some(self.stack.bottom()));
-                unsafe {
+                self.running = true;
+                let result = unsafe {
                     fiber_enter(
                         self.stack.top(),
                         core::ptr::addr_of_mut!(payload) as usize,
                         enter::<Y, R, Resume>,
                     )
-                }
+                };
+                std::mem::forget(arg);
+                result
             }
             Some(v) => {
                 #[cfg(feature = "stacker")]
                 stacker::set_stack_limit(self.stack_limit);
-                unsafe { fiber_switch_enter(v, payload) }
+                self.running = true;
+                let result = unsafe { fiber_switch_enter(v, &arg as *const _ as usize) };
+                std::mem::forget(arg);
+                result
             }
         };
-        std::mem::forget(arg);
+        self.running = false;
         self.result = result.stack;
+
         #[cfg(feature = "stacker")]
         {
             self.stack_limit = stacker::get_stack_limit();
@@ -146,17 +312,122 @@ impl<Y, R, Resume> Generator<Resume> for StackfulGenerator<'_, Y, R, Resume> {
         let y_payload = unsafe { (result.payload as *const YieldPayload).read() };
 
         match y_payload {
-            YieldPayload::Yielded(y) => GeneratorState::Yielded(unsafe { (y as *const Y).read() }),
+            YieldPayload::Yielded(y) => Ok(Switched::State(GeneratorState::Yielded(unsafe {
+                (y as *const Y).read()
+            }))),
             YieldPayload::Complete(r) => {
                 self.result = None;
-                GeneratorState::Complete(unsafe { (r as *const R).read() })
+                self.locals.clear();
+                Ok(Switched::State(GeneratorState::Complete(unsafe {
+                    (r as *const R).read()
+                })))
             }
+            YieldPayload::Transfer(target, arg) => Ok(Switched::Transfer(target, unsafe {
+                (arg as *const Resume).read()
+            })),
             YieldPayload::Panic(p) => {
                 self.result = None;
-                std::panic::resume_unwind(unsafe { Box::from_raw(p) });
+                self.poisoned = true;
+                self.locals.clear();
+                Err(unsafe { Box::from_raw(p) })
             }
         }
     }
+
+    /// Resume the generator, returning the payload as an error if it panics.
+    ///
+    /// A generator that has panicked is poisoned and can't be resumed again.
+    pub fn try_resume(
+        self: Pin<&mut Self>,
+        arg: Resume,
+    ) -> Result<GeneratorState<Y, R>, PanicPayload> {
+        self.get_mut()
+            .resume_with(ResumePayload::Resumed(arg))
+            .map(into_generator_state)
+    }
+
+    /// Resume a generator created with `new_symmetric`.
+    ///
+    /// Unlike `resume`, this can report that a peer the generator transferred control to has
+    /// returned, in which case the generator itself is still suspended.
+    pub fn resume_transfer(self: Pin<&mut Self>, arg: Resume) -> TransferState<Y, R> {
+        self.get_mut()
+            .resume_with(ResumePayload::Resumed(arg))
+            .unwrap_or_else(|p| std::panic::resume_unwind(p))
+    }
+
+    /// Resume the generator, or return an error if it's not in a resumable state.
+    pub fn resume_checked(
+        self: Pin<&mut Self>,
+        arg: Resume,
+    ) -> Result<GeneratorState<Y, R>, ResumeError> {
+        match self.status() {
+            GeneratorStatus::NotStarted | GeneratorStatus::Suspended => Ok(self.resume(arg)),
+            GeneratorStatus::Running => Err(ResumeError::Running),
+            GeneratorStatus::Completed => Err(ResumeError::Completed),
+            GeneratorStatus::Panicked => Err(ResumeError::Panicked),
+        }
+    }
+
+    /// Check if the generator has panicked.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Get the current status of the generator.
+    pub fn status(&self) -> GeneratorStatus {
+        if self.running {
+            GeneratorStatus::Running
+        } else if self.func.is_some() {
+            GeneratorStatus::NotStarted
+        } else if self.result.is_some() {
+            GeneratorStatus::Suspended
+        } else if self.poisoned {
+            GeneratorStatus::Panicked
+        } else {
+            GeneratorStatus::Completed
+        }
+    }
+
+    /// Resume the generator by raising a panic from the `yeet` call that it is suspended in.
+    ///
+    /// The generator may catch the panic, run its cleanup and carry on, in which case this
+    /// returns the next yielded or returned value. Otherwise the panic propagates out of this
+    /// call. If the generator hasn't started yet, the panic is raised without running it.
+    pub fn resume_with_panic(self: Pin<&mut Self>, payload: PanicPayload) -> GeneratorState<Y, R> {
+        self.get_mut()
+            .resume_with(ResumePayload::Panic(payload))
+            .map(into_generator_state)
+            .unwrap_or_else(|p| std::panic::resume_unwind(p))
+    }
+}
+
+fn into_generator_state<Y, R>(state: TransferState<Y, R>) -> GeneratorState<Y, R> {
+    match state {
+        TransferState::Yielded(y) => GeneratorState::Yielded(y),
+        TransferState::Complete(r) => GeneratorState::Complete(r),
+        TransferState::PeerComplete(_) => {
+            panic!("a generator transferred to has returned; use `resume_transfer` to handle this")
+        }
+    }
+}
+
+impl<Y, R, T, E> StackfulGenerator<'_, Y, R, Result<T, E>> {
+    /// Resume the generator with an error, which is returned from the `yeet` call that it is
+    /// suspended in.
+    pub fn resume_err(self: Pin<&mut Self>, err: E) -> GeneratorState<Y, R> {
+        self.resume(Err(err))
+    }
+}
+
+impl<Y, R, Resume> Generator<Resume> for StackfulGenerator<'_, Y, R, Resume> {
+    type Yield = Y;
+    type Return = R;
+
+    fn resume(self: Pin<&mut Self>, arg: Resume) -> GeneratorState<Y, R> {
+        self.try_resume(arg)
+            .unwrap_or_else(|p| std::panic::resume_unwind(p))
+    }
 }
 
 impl<Y, Resume> YieldHandle<Y, Resume> {
@@ -168,15 +439,170 @@ impl<Y, Resume> YieldHandle<Y, Resume> {
             let payload = YieldPayload::Yielded(&arg as *const Y as _);
             let result = fiber_switch_leave(self.stack.get(), &payload as *const YieldPayload as _);
             std::mem::forget(arg);
+            self.resumed(result)
+        }
+    }
 
-            self.stack.set(result.stack.unwrap());
-            if result.payload == 0 {
-                std::panic::resume_unwind(Box::new(DropPanic));
+    /// Delegate to another generator until it returns, like Python's `yield from`.
+    ///
+    /// `gen` is first resumed with `arg`. Each value it yields is yielded from the current
+    /// generator, and `gen` is then resumed with the argument the current generator gets resumed
+    /// with. Returns the value returned by `gen`.
+    pub fn yield_from<G>(&self, mut gen: G, mut arg: Resume) -> G::Return
+    where
+        G: Generator<Resume, Yield = Y> + Unpin,
+    {
+        loop {
+            match Pin::new(&mut gen).resume(arg) {
+                GeneratorState::Yielded(y) => arg = self.yeet(y),
+                GeneratorState::Complete(r) => return r,
             }
-            let r = (result.payload as *mut Resume).read();
-            r
         }
     }
+
+    unsafe fn resumed(&self, result: SwitchResult) -> Resume {
+        self.stack.set(result.stack.unwrap());
+        if result.payload == 0 {
+            CANCELLING.with(|c| c.set(true));
+            std::panic::resume_unwind(Box::new(DropPanic));
+        }
+        match (result.payload as *mut ResumePayload<Resume>).read() {
+            ResumePayload::Resumed(r) => r,
+            ResumePayload::Panic(p) => std::panic::resume_unwind(p),
+        }
+    }
+}
+
+impl<Y, R, Resume> TransferHandle<Y, R, Resume> {
+    /// Suspend the current generator and resume `other` with `arg`, without going back to the
+    /// code that resumed the current generator.
+    ///
+    /// `other` runs on behalf of the `resume` call that is running the current generator: the
+    /// next value yielded by `other`, or by any generator it transfers to, is what that `resume`
+    /// call returns. If one of them returns instead, `resume_transfer` reports it as
+    /// `PeerComplete`, while `resume` panics, as the generator it was called on is still
+    /// suspended.
+    ///
+    /// The current generator stays suspended in `transfer` until it's resumed again, either by
+    /// `resume` or by another `transfer`, and the argument it's resumed with is returned.
+    ///
+    /// The `resume` call panics if `other` is running or has completed.
+    pub fn transfer(
+        &self,
+        other: &RefCell<StackfulGenerator<'_, Y, R, Resume>>,
+        arg: Resume,
+    ) -> Resume {
+        unsafe {
+            let payload = YieldPayload::Transfer(
+                other as *const _ as *const (),
+                &arg as *const Resume as *const (),
+            );
+            let result =
+                fiber_switch_leave(self.inner.stack.get(), &payload as *const YieldPayload as _);
+            std::mem::forget(arg);
+            self.inner.resumed(result)
+        }
+    }
+}
+
+impl<Y, R, Resume> Deref for TransferHandle<Y, R, Resume> {
+    type Target = YieldHandle<Y, Resume>;
+
+    fn deref(&self) -> &YieldHandle<Y, Resume> {
+        &self.inner
+    }
+}
+
+/// A family of resume argument types that can borrow from the caller of `resume`.
+pub trait ResumeArg {
+    type Arg<'r>
+    where
+        Self: 'r;
+}
+
+/// Resume with `&'r T`.
+pub struct Borrowed<T: ?Sized>(PhantomData<*const T>);
+
+impl<T: ?Sized> ResumeArg for Borrowed<T> {
+    type Arg<'r>
+        = &'r T
+    where
+        Self: 'r;
+}
+
+/// Resume with `&'r mut T`.
+pub struct BorrowedMut<T: ?Sized>(PhantomData<*mut T>);
+
+impl<T: ?Sized> ResumeArg for BorrowedMut<T> {
+    type Arg<'r>
+        = &'r mut T
+    where
+        Self: 'r;
+}
+
+/// A generator whose resume argument may borrow data that only lives for one `resume` call.
+///
+/// The argument is handed to the generator through `BorrowingYieldHandle`, which only lends it
+/// out until the generator yields again.
+pub struct BorrowingGenerator<'a, Y, R, A: ResumeArg> {
+    // Resumed with a pointer to an `Option<A::Arg<'_>>` on the stack of `resume`.
+    inner: StackfulGenerator<'a, Y, R, usize>,
+    _marker: PhantomData<A>,
+}
+
+impl<Y, R, A: ResumeArg> Unpin for BorrowingGenerator<'_, Y, R, A> {}
+
+pub struct BorrowingYieldHandle<'y, Y, A: ResumeArg> {
+    inner: &'y YieldHandle<Y, usize>,
+    slot: usize,
+    _marker: PhantomData<A>,
+}
+
+impl<'a, Y, R, A: ResumeArg> BorrowingGenerator<'a, Y, R, A> {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: FnOnce(&mut BorrowingYieldHandle<'_, Y, A>) -> R + 'a,
+    {
+        Self {
+            inner: StackfulGenerator::new(move |y: &YieldHandle<Y, usize>, slot: usize| {
+                f(&mut BorrowingYieldHandle {
+                    inner: y,
+                    slot,
+                    _marker: PhantomData,
+                })
+            }),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn resume(self: Pin<&mut Self>, arg: A::Arg<'_>) -> GeneratorState<Y, R> {
+        let mut slot = Some(arg);
+        Pin::new(&mut self.get_mut().inner).resume(&mut slot as *mut _ as usize)
+    }
+
+    /// Get the current status of the generator.
+    pub fn status(&self) -> GeneratorStatus {
+        self.inner.status()
+    }
+}
+
+impl<Y, A: ResumeArg> BorrowingYieldHandle<'_, Y, A> {
+    /// Take the argument that the generator was last resumed with.
+    ///
+    /// This is how the generator gets the argument of the first `resume`. Returns `None` if the
+    /// argument has already been taken, e.g. because it was returned from `yeet`.
+    pub fn take_arg(&mut self) -> Option<A::Arg<'_>> {
+        // SAFETY: The slot lives on the stack of the `resume` call that is running the generator,
+        // which is valid until it yields. Yielding needs `&mut self` so the argument can't be
+        // used after that.
+        unsafe { (*(self.slot as *mut Option<A::Arg<'_>>)).take() }
+    }
+
+    /// Yield a value, and return the argument that the generator is resumed with next.
+    pub fn yeet(&mut self, arg: Y) -> A::Arg<'_> {
+        self.slot = self.inner.yeet(arg);
+        self.take_arg().unwrap()
+    }
 }
 
 #[test]
@@ -199,3 +625,197 @@ fn test_generator() {
         GeneratorState::Complete(1024)
     ));
 }
+
+#[test]
+fn test_resume_with_panic() {
+    let mut gen = StackfulGenerator::new(|y: &YieldHandle<i32, ()>, ()| {
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| y.yeet(1))).unwrap_err();
+        assert_eq!(*err.downcast::<&str>().unwrap(), "timeout");
+        y.yeet(2);
+        3
+    });
+    let mut gen = Pin::new(&mut gen);
+
+    assert!(matches!(
+        gen.as_mut().resume(()),
+        GeneratorState::Yielded(1)
+    ));
+    assert!(matches!(
+        gen.as_mut().resume_with_panic(Box::new("timeout")),
+        GeneratorState::Yielded(2)
+    ));
+    assert!(matches!(
+        gen.as_mut().resume(()),
+        GeneratorState::Complete(3)
+    ));
+}
+
+#[test]
+fn test_resume_err() {
+    let mut gen = StackfulGenerator::new(|y: &YieldHandle<(), Result<i32, &str>>, r| {
+        assert_eq!(r, Ok(1));
+        y.yeet(()).unwrap_or(-1)
+    });
+    let mut gen = Pin::new(&mut gen);
+
+    assert!(matches!(
+        gen.as_mut().resume(Ok(1)),
+        GeneratorState::Yielded(())
+    ));
+    assert!(matches!(
+        gen.as_mut().resume_err("protocol error"),
+        GeneratorState::Complete(-1)
+    ));
+}
+
+#[test]
+fn test_try_resume() {
+    let mut gen = StackfulGenerator::new(|y: &YieldHandle<(), ()>, ()| {
+        y.yeet(());
+        panic!("plugin failure");
+    });
+    let mut gen = Pin::new(&mut gen);
+
+    assert!(matches!(
+        gen.as_mut().try_resume(()),
+        Ok(GeneratorState::Yielded(()))
+    ));
+    let err = gen.as_mut().try_resume(()).unwrap_err();
+    assert_eq!(*err.downcast::<&str>().unwrap(), "plugin failure");
+    assert!(gen.is_poisoned());
+}
+
+#[test]
+fn test_status() {
+    let mut gen = StackfulGenerator::new(|y: &YieldHandle<(), ()>, ()| y.yeet(()));
+    let mut gen = Pin::new(&mut gen);
+
+    assert_eq!(gen.status(), GeneratorStatus::NotStarted);
+    assert!(matches!(
+        gen.as_mut().resume_checked(()),
+        Ok(GeneratorState::Yielded(()))
+    ));
+    assert_eq!(gen.status(), GeneratorStatus::Suspended);
+    assert!(matches!(
+        gen.as_mut().resume_checked(()),
+        Ok(GeneratorState::Complete(()))
+    ));
+    assert_eq!(gen.status(), GeneratorStatus::Completed);
+    assert_eq!(gen.as_mut().resume_checked(()), Err(ResumeError::Completed));
+
+    let mut gen = StackfulGenerator::new(|_: &YieldHandle<(), ()>, ()| panic!());
+    let mut gen = Pin::new(&mut gen);
+    assert!(gen.as_mut().try_resume(()).is_err());
+    assert_eq!(gen.status(), GeneratorStatus::Panicked);
+    assert_eq!(gen.as_mut().resume_checked(()), Err(ResumeError::Panicked));
+}
+
+#[test]
+fn test_transfer() {
+    use std::cell::OnceCell;
+    use std::rc::{Rc, Weak};
+
+    type Gen = StackfulGenerator<'static, i32, i32, i32>;
+    let ping: Rc<OnceCell<RefCell<Gen>>> = Rc::default();
+    let pong: Rc<OnceCell<RefCell<Gen>>> = Rc::default();
+
+    let peer = Rc::downgrade(&pong);
+    let _ = ping.set(RefCell::new(Gen::new_symmetric(move |y, mut n| {
+        while n < 10 {
+            n = y.transfer(peer.upgrade().unwrap().get().unwrap(), n + 1);
+        }
+        n
+    })));
+    let peer: Weak<OnceCell<RefCell<Gen>>> = Rc::downgrade(&ping);
+    let _ = pong.set(RefCell::new(Gen::new_symmetric(move |y, mut n| loop {
+        if n == 5 {
+            n = y.yeet(n);
+        }
+        n = y.transfer(peer.upgrade().unwrap().get().unwrap(), n + 1);
+    })));
+
+    let mut gen = ping.get().unwrap().borrow_mut();
+    let mut gen = Pin::new(&mut *gen);
+    // The yield from `pong` is returned by the resume call on `ping`.
+    assert!(matches!(gen.as_mut().resume(0), GeneratorState::Yielded(5)));
+    assert_eq!(gen.status(), GeneratorStatus::Suspended);
+    // `ping` is still suspended in `transfer`, and resuming it transfers to `pong` again.
+    assert!(matches!(
+        gen.as_mut().resume(5),
+        GeneratorState::Complete(11)
+    ));
+}
+
+#[test]
+fn test_transfer_peer_complete() {
+    use std::cell::OnceCell;
+    use std::rc::Rc;
+
+    type Gen = StackfulGenerator<'static, (), i32, i32>;
+    let done: Rc<OnceCell<RefCell<Gen>>> = Rc::default();
+    let _ = done.set(RefCell::new(Gen::new_symmetric(|_, n| n * 2)));
+
+    let peer = done.clone();
+    let mut gen = Gen::new_symmetric(move |y, n| y.transfer(peer.get().unwrap(), n) + 1);
+    let mut gen = Pin::new(&mut gen);
+    assert_eq!(
+        gen.as_mut().resume_transfer(2),
+        TransferState::PeerComplete(4)
+    );
+    // The generator itself hasn't completed.
+    assert_eq!(gen.status(), GeneratorStatus::Suspended);
+    assert_eq!(gen.as_mut().resume_transfer(4), TransferState::Complete(5));
+}
+
+#[test]
+fn test_yield_from() {
+    let mut gen = StackfulGenerator::new(|y: &YieldHandle<i32, i32>, r: i32| {
+        let inner = StackfulGenerator::new(|y: &YieldHandle<i32, i32>, r: i32| {
+            let r = y.yeet(r + 1);
+            y.yeet(r + 1) * 10
+        });
+        let r = y.yield_from(inner, y.yeet(r));
+        y.yeet(r)
+    });
+    let mut gen = Pin::new(&mut gen);
+
+    assert!(matches!(gen.as_mut().resume(1), GeneratorState::Yielded(1)));
+    assert!(matches!(gen.as_mut().resume(2), GeneratorState::Yielded(3)));
+    assert!(matches!(gen.as_mut().resume(4), GeneratorState::Yielded(5)));
+    assert!(matches!(
+        gen.as_mut().resume(6),
+        GeneratorState::Yielded(60)
+    ));
+    assert!(matches!(
+        gen.as_mut().resume(7),
+        GeneratorState::Complete(7)
+    ));
+}
+
+#[test]
+fn test_borrowing_generator() {
+    let mut gen = BorrowingGenerator::new(
+        |y: &mut BorrowingYieldHandle<'_, usize, BorrowedMut<Vec<i32>>>| {
+            let v = y.take_arg().unwrap();
+            v.push(1);
+            let len = v.len();
+            let v = y.yeet(len);
+            v.push(2);
+            v.len()
+        },
+    );
+    let mut gen = Pin::new(&mut gen);
+
+    let mut a = Vec::new();
+    assert!(matches!(
+        gen.as_mut().resume(&mut a),
+        GeneratorState::Yielded(1)
+    ));
+    let mut b = vec![0];
+    assert!(matches!(
+        gen.as_mut().resume(&mut b),
+        GeneratorState::Complete(2)
+    ));
+    assert_eq!(a, [1]);
+    assert_eq!(b, [0, 2]);
+}
@@ -0,0 +1,430 @@
+//! Adapters between sync and async IO traits.
+
+use crate::future::{wait, StackfulFuture};
+use crate::generator::*;
+
+use core::convert::Infallible;
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{ready, Context, Poll};
+
+use futures_io::{AsyncRead, AsyncWrite};
+
+// Upper bound of the internal buffers, so a huge read or write doesn't allocate unboundedly.
+const MAX_BUF: usize = 64 * 1024;
+
+type Op<T> = fn(&mut T, &Shared<T>) -> io::Result<usize>;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Read,
+    Write,
+    Flush,
+}
+
+struct ReadBuf {
+    data: Vec<u8>,
+    pos: usize,
+    filled: usize,
+}
+
+struct Shared<T> {
+    // Operation posted for the fiber to pick up.
+    op: Cell<Option<Op<T>>>,
+    // Result of the operation, set by the fiber once it's done.
+    done: Cell<Option<io::Result<usize>>>,
+    read_buf: RefCell<ReadBuf>,
+    write_buf: RefCell<Vec<u8>>,
+}
+
+/// Expose a synchronous reader or writer as `AsyncRead` or `AsyncWrite`.
+///
+/// Each operation is run on a fiber that lives as long as this adapter. If the wrapped object
+/// calls `wait` then the operation returns `Pending` instead of blocking the current thread.
+///
+/// Writes are buffered: `poll_write` hands the data to the fiber and returns immediately, and
+/// an error from the underlying writer is reported by the next `poll_write` or `poll_flush`.
+/// Dropping the adapter cancels any write that is still in progress, so it should be flushed
+/// before being dropped.
+pub struct AsyncFromSync<T> {
+    shared: Rc<Shared<T>>,
+    fiber: StackfulFuture<'static, Infallible>,
+    in_flight: Option<Kind>,
+    read_eof: bool,
+    read_err: Option<io::Error>,
+    write_err: Option<io::Error>,
+    flush_done: bool,
+    // `T` is owned by the fiber, whose lifetime is erased, so tell dropck that we drop a `T`.
+    // Boxed so that the adapter stays `Unpin`, as `T` is never pinned.
+    _marker: PhantomData<Box<T>>,
+}
+
+impl<T> AsyncFromSync<T> {
+    pub fn new(inner: T) -> Self {
+        let shared = Rc::new(Shared {
+            op: Cell::new(None),
+            done: Cell::new(None),
+            read_buf: RefCell::new(ReadBuf {
+                data: Vec::new(),
+                pos: 0,
+                filled: 0,
+            }),
+            write_buf: RefCell::new(Vec::new()),
+        });
+        let fiber_shared = shared.clone();
+        let fiber = StackfulFuture::new(move || -> Infallible {
+            let mut inner = inner;
+            loop {
+                let op = wait(core::future::poll_fn(|_| match fiber_shared.op.take() {
+                    Some(op) => Poll::Ready(op),
+                    None => Poll::Pending,
+                }));
+                let result = op(&mut inner, &fiber_shared);
+                fiber_shared.done.set(Some(result));
+            }
+        });
+        // SAFETY: The fiber only captures `T`, which is owned by `self`. The fiber is dropped
+        // together with `self`, so it never outlives any lifetime contained in `T`.
+        let fiber = unsafe {
+            core::mem::transmute::<
+                StackfulFuture<'_, Infallible>,
+                StackfulFuture<'static, Infallible>,
+            >(fiber)
+        };
+        Self {
+            shared,
+            fiber,
+            in_flight: None,
+            read_eof: false,
+            read_err: None,
+            write_err: None,
+            flush_done: false,
+            _marker: PhantomData,
+        }
+    }
+
+    fn start(&mut self, kind: Kind, op: Op<T>) {
+        debug_assert!(self.in_flight.is_none());
+        self.shared.op.set(Some(op));
+        self.in_flight = Some(kind);
+    }
+
+    // Resume the fiber until the operation in flight completes.
+    fn drive(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        match Pin::new(&mut self.fiber).poll(cx) {
+            Poll::Ready(never) => match never {},
+            Poll::Pending => (),
+        }
+        let result = match self.shared.done.take() {
+            Some(v) => v,
+            None => return Poll::Pending,
+        };
+        match (self.in_flight.take().unwrap(), result) {
+            (Kind::Read, Ok(n)) => {
+                self.shared.read_buf.borrow_mut().filled = n;
+                self.read_eof = n == 0;
+            }
+            (Kind::Read, Err(err)) => self.read_err = Some(err),
+            (Kind::Write, Ok(_)) => (),
+            (Kind::Flush, Ok(_)) => self.flush_done = true,
+            (Kind::Write, Err(err)) | (Kind::Flush, Err(err)) => self.write_err = Some(err),
+        }
+        Poll::Ready(())
+    }
+}
+
+fn read_op<T: Read>(inner: &mut T, shared: &Shared<T>) -> io::Result<usize> {
+    let mut buf = shared.read_buf.borrow_mut();
+    inner.read(&mut buf.data)
+}
+
+fn write_op<T: Write>(inner: &mut T, shared: &Shared<T>) -> io::Result<usize> {
+    let buf = shared.write_buf.borrow();
+    inner.write_all(&buf)?;
+    Ok(buf.len())
+}
+
+fn flush_op<T: Write>(inner: &mut T, _: &Shared<T>) -> io::Result<usize> {
+    inner.flush()?;
+    Ok(0)
+}
+
+impl<T: Read> AsyncRead for AsyncFromSync<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            if this.in_flight.is_none() {
+                let mut read_buf = this.shared.read_buf.borrow_mut();
+                if read_buf.pos < read_buf.filled {
+                    let len = buf.len().min(read_buf.filled - read_buf.pos);
+                    buf[..len].copy_from_slice(&read_buf.data[read_buf.pos..][..len]);
+                    read_buf.pos += len;
+                    return Poll::Ready(Ok(len));
+                }
+                if core::mem::take(&mut this.read_eof) {
+                    return Poll::Ready(Ok(0));
+                }
+                if let Some(err) = this.read_err.take() {
+                    return Poll::Ready(Err(err));
+                }
+                if buf.is_empty() {
+                    return Poll::Ready(Ok(0));
+                }
+                read_buf.data.resize(buf.len().min(MAX_BUF), 0);
+                read_buf.pos = 0;
+                read_buf.filled = 0;
+                drop(read_buf);
+                this.start(Kind::Read, read_op::<T>);
+            }
+            ready!(this.drive(cx));
+        }
+    }
+}
+
+impl<T: Write> AsyncWrite for AsyncFromSync<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(err) = this.write_err.take() {
+                return Poll::Ready(Err(err));
+            }
+            if this.in_flight.is_none() {
+                if buf.is_empty() {
+                    return Poll::Ready(Ok(0));
+                }
+                let len = buf.len().min(MAX_BUF);
+                {
+                    let mut write_buf = this.shared.write_buf.borrow_mut();
+                    write_buf.clear();
+                    write_buf.extend_from_slice(&buf[..len]);
+                }
+                this.flush_done = false;
+                this.start(Kind::Write, write_op::<T>);
+                // Get the write going; its outcome is reported by a later call.
+                let _ = this.drive(cx);
+                return Poll::Ready(Ok(len));
+            }
+            ready!(this.drive(cx));
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.in_flight.is_none() {
+                if let Some(err) = this.write_err.take() {
+                    return Poll::Ready(Err(err));
+                }
+                if core::mem::take(&mut this.flush_done) {
+                    return Poll::Ready(Ok(()));
+                }
+                this.start(Kind::Flush, flush_op::<T>);
+            }
+            ready!(this.drive(cx));
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+/// Outcome of feeding data into a [`PushReader`].
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+pub enum PushState<T> {
+    /// The consumer has used up all data and is waiting for more.
+    Pending,
+    /// The consumer has returned.
+    Done(T),
+    /// The consumer returned earlier, and its output has already been handed out.
+    Finished,
+}
+
+// The chunk currently being consumed, or `None` at end of input. The pointer is only valid while
+// the `feed` call that supplied it is running.
+type Chunk = Option<*const [u8]>;
+
+struct Pipe<'y> {
+    yielder: &'y YieldHandle<(), Chunk>,
+    chunk: Chunk,
+}
+
+impl Read for Pipe<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let chunk = match self.chunk {
+                Some(v) => unsafe { &*v },
+                None => return Ok(0),
+            };
+            if !chunk.is_empty() || buf.is_empty() {
+                let len = buf.len().min(chunk.len());
+                buf[..len].copy_from_slice(&chunk[..len]);
+                self.chunk = Some(&chunk[len..]);
+                return Ok(len);
+            }
+            // Out of data; ask for more. This also makes sure the chunk is fully consumed before
+            // `feed` returns, so it's never accessed afterwards.
+            self.chunk = self.yielder.yeet(());
+        }
+    }
+}
+
+/// Drive a pull-based consumer of `Read` with data pushed from the outside.
+///
+/// The consumer runs on its own fiber. Whenever it tries to read but all data fed so far has been
+/// consumed, control returns to the caller of `feed`.
+pub struct PushReader<'a, T> {
+    generator: StackfulGenerator<'a, (), (T, usize), Chunk>,
+    leftover: usize,
+    done: bool,
+}
+
+impl<'a, T> PushReader<'a, T> {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: FnOnce(&mut dyn Read) -> T + 'a,
+    {
+        Self {
+            generator: StackfulGenerator::new(move |y: &YieldHandle<(), Chunk>, chunk: Chunk| {
+                let mut pipe = Pipe { yielder: y, chunk };
+                let output = f(&mut pipe);
+                let leftover = pipe.chunk.map_or(0, |v| unsafe { &*v }.len());
+                (output, leftover)
+            }),
+            leftover: 0,
+            done: false,
+        }
+    }
+
+    fn resume(&mut self, chunk: Chunk) -> PushState<T> {
+        if self.done {
+            return PushState::Finished;
+        }
+        match Pin::new(&mut self.generator).resume(chunk) {
+            GeneratorState::Yielded(()) => PushState::Pending,
+            GeneratorState::Complete((output, leftover)) => {
+                self.leftover = leftover;
+                self.done = true;
+                PushState::Done(output)
+            }
+        }
+    }
+
+    /// Feed more data to the consumer.
+    ///
+    /// Returns `Pending` if all of `data` has been consumed and the consumer wants more, or
+    /// `Finished` if the consumer has already returned.
+    pub fn feed(&mut self, data: &[u8]) -> PushState<T> {
+        self.resume(Some(data))
+    }
+
+    /// Signal end of input to the consumer.
+    ///
+    /// Reads after the end of input return 0 bytes without suspending, so this returns `Done`
+    /// with the consumer's output, or `Finished` if the consumer has already returned.
+    pub fn finish(&mut self) -> PushState<T> {
+        self.resume(None)
+    }
+
+    /// Number of bytes from the last call to `feed` that were not consumed when the consumer
+    /// returned.
+    pub fn leftover(&self) -> usize {
+        self.leftover
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+struct Slow<T>(T);
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+impl<T: Read> Read for Slow<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        wait(async_std::task::yield_now());
+        let len = buf.len().min(3);
+        self.0.read(&mut buf[..len])
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+impl<T: Write> Write for Slow<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        wait(async_std::task::yield_now());
+        self.0.write(&buf[..buf.len().min(3)])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        wait(async_std::task::yield_now());
+        self.0.flush()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+fn read() {
+    use futures::io::AsyncReadExt;
+
+    async_std::task::block_on(async {
+        let mut reader = AsyncFromSync::new(Slow(&b"hello world"[..]));
+        let mut output = String::new();
+        reader.read_to_string(&mut output).await.unwrap();
+        assert_eq!(output, "hello world");
+    });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+fn write() {
+    use futures::io::AsyncWriteExt;
+
+    let mut output = Vec::new();
+    async_std::task::block_on(async {
+        let mut writer = AsyncFromSync::new(Slow(&mut output));
+        writer.write_all(b"hello ").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+        writer.flush().await.unwrap();
+    });
+    assert_eq!(output, b"hello world");
+}
+
+#[test]
+fn push_reader() {
+    let mut reader = PushReader::new(|r: &mut dyn Read| {
+        let mut output = String::new();
+        r.read_to_string(&mut output).unwrap();
+        output
+    });
+    assert_eq!(reader.feed(b"hello "), PushState::Pending);
+    assert_eq!(reader.feed(b""), PushState::Pending);
+    assert_eq!(reader.feed(b"world"), PushState::Pending);
+    assert_eq!(reader.finish(), PushState::Done("hello world".to_owned()));
+    assert_eq!(reader.finish(), PushState::Finished);
+}
+
+#[test]
+fn push_reader_leftover() {
+    let mut reader = PushReader::new(|r: &mut dyn Read| {
+        let mut header = [0; 4];
+        r.read_exact(&mut header).unwrap();
+        header
+    });
+    assert_eq!(reader.feed(b"ab"), PushState::Pending);
+    assert_eq!(reader.feed(b"cdef"), PushState::Done(*b"abcd"));
+    assert_eq!(reader.leftover(), 2);
+    assert_eq!(reader.feed(b"gh"), PushState::Finished);
+    assert_eq!(reader.finish(), PushState::Finished);
+}
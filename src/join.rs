@@ -0,0 +1,241 @@
+use crate::future::StackfulFuture;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// Futures are pinned in place for their whole life, so boxing the fiber would only add an allocation.
+#[allow(clippy::large_enum_variant)]
+enum MaybeDone<'a, T> {
+    Running(StackfulFuture<'a, T>),
+    Done(T),
+    Taken,
+}
+
+impl<'a, T> MaybeDone<'a, T> {
+    fn new(f: impl FnOnce() -> T + 'a) -> Self {
+        MaybeDone::Running(StackfulFuture::new(f))
+    }
+
+    // Poll the fiber if it is still running, and return whether it's done.
+    fn poll(&mut self, cx: &mut Context<'_>) -> bool {
+        if let MaybeDone::Running(fut) = self {
+            match Pin::new(fut).poll(cx) {
+                Poll::Ready(val) => *self = MaybeDone::Done(val),
+                Poll::Pending => return false,
+            }
+        }
+        true
+    }
+
+    fn take(&mut self) -> T {
+        match std::mem::replace(self, MaybeDone::Taken) {
+            MaybeDone::Done(val) => val,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Future returned by [`join`].
+pub struct Join<'a, A, B> {
+    a: MaybeDone<'a, A>,
+    b: MaybeDone<'a, B>,
+}
+
+// Outputs are never pinned.
+impl<A, B> Unpin for Join<'_, A, B> {}
+
+impl<A, B> Future for Join<'_, A, B> {
+    type Output = (A, B);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<(A, B)> {
+        let a = self.a.poll(cx);
+        let b = self.b.poll(cx);
+        if a && b {
+            Poll::Ready((self.a.take(), self.b.take()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Run two functions concurrently on separate fibers, and return both results.
+///
+/// Whenever one of them calls `wait` on a future that isn't ready, the other one gets to run.
+/// No tasks are spawned; both run within the returned future.
+pub fn join<'a, A, B>(a: impl FnOnce() -> A + 'a, b: impl FnOnce() -> B + 'a) -> Join<'a, A, B> {
+    Join {
+        a: MaybeDone::new(a),
+        b: MaybeDone::new(b),
+    }
+}
+
+/// Future returned by [`join_all`].
+pub struct JoinAll<'a, T> {
+    fibers: Vec<MaybeDone<'a, T>>,
+}
+
+impl<T> Unpin for JoinAll<'_, T> {}
+
+impl<T> Future for JoinAll<'_, T> {
+    type Output = Vec<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Vec<T>> {
+        let mut done = true;
+        for fiber in self.fibers.iter_mut() {
+            done &= fiber.poll(cx);
+        }
+        if done {
+            Poll::Ready(self.fibers.iter_mut().map(MaybeDone::take).collect())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Run all functions concurrently on separate fibers, and return their results in order.
+pub fn join_all<'a, T, F>(fs: impl IntoIterator<Item = F>) -> JoinAll<'a, T>
+where
+    F: FnOnce() -> T + 'a,
+{
+    JoinAll {
+        fibers: fs.into_iter().map(MaybeDone::new).collect(),
+    }
+}
+
+/// Result of [`select`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+/// Future returned by [`select`].
+pub struct Select<'a, A, B> {
+    a: StackfulFuture<'a, A>,
+    b: StackfulFuture<'a, B>,
+}
+
+impl<A, B> Future for Select<'_, A, B> {
+    type Output = Either<A, B>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Either<A, B>> {
+        if let Poll::Ready(val) = Pin::new(&mut self.a).poll(cx) {
+            return Poll::Ready(Either::Left(val));
+        }
+        if let Poll::Ready(val) = Pin::new(&mut self.b).poll(cx) {
+            return Poll::Ready(Either::Right(val));
+        }
+        Poll::Pending
+    }
+}
+
+/// Run two functions concurrently on separate fibers, and return the result of whichever
+/// finishes first.
+///
+/// The other function is cancelled when the returned future is dropped, unwinding its stack as if
+/// its `StackfulFuture` were dropped.
+pub fn select<'a, A, B>(
+    a: impl FnOnce() -> A + 'a,
+    b: impl FnOnce() -> B + 'a,
+) -> Select<'a, A, B> {
+    Select {
+        a: StackfulFuture::new(a),
+        b: StackfulFuture::new(b),
+    }
+}
+
+/// Future returned by [`select_all`].
+pub struct SelectAll<'a, T> {
+    fibers: Vec<StackfulFuture<'a, T>>,
+}
+
+impl<T> Future for SelectAll<'_, T> {
+    type Output = (usize, T);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<(usize, T)> {
+        for (i, fiber) in self.fibers.iter_mut().enumerate() {
+            if let Poll::Ready(val) = Pin::new(fiber).poll(cx) {
+                return Poll::Ready((i, val));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// Run all functions concurrently on separate fibers, and return the index of the first one to
+/// complete along with its result, like `wait_any`.
+///
+/// See [`select`] for how the remaining functions are cancelled.
+pub fn select_all<'a, T, F>(fs: impl IntoIterator<Item = F>) -> SelectAll<'a, T>
+where
+    F: FnOnce() -> T + 'a,
+{
+    let fibers: Vec<_> = fs.into_iter().map(StackfulFuture::new).collect();
+    assert!(!fibers.is_empty(), "`select_all` called with no functions");
+    SelectAll { fibers }
+}
+
+#[test]
+fn test_join() {
+    use crate::yield_now;
+    use std::cell::RefCell;
+
+    let log = RefCell::new(Vec::new());
+    let run = |name| {
+        let log = &log;
+        move || {
+            for i in 0..2 {
+                log.borrow_mut().push((name, i));
+                yield_now();
+            }
+            name
+        }
+    };
+    assert_eq!(
+        futures_executor::block_on(join(run("a"), run("b"))),
+        ("a", "b")
+    );
+    assert_eq!(*log.borrow(), [("a", 0), ("b", 0), ("a", 1), ("b", 1)]);
+
+    let all = futures_executor::block_on(join_all((0..3).map(|i| move || i * 2)));
+    assert_eq!(all, [0, 2, 4]);
+}
+
+#[test]
+fn test_select() {
+    use crate::yield_now;
+    use std::cell::Cell;
+
+    let cancelled = Cell::new(false);
+    let slow = || {
+        struct Guard<'a>(&'a Cell<bool>);
+        impl Drop for Guard<'_> {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+        let _guard = Guard(&cancelled);
+        loop {
+            yield_now();
+        }
+    };
+    let fast = || {
+        yield_now();
+        1
+    };
+    assert_eq!(
+        futures_executor::block_on(select(slow, fast)),
+        Either::<(), _>::Right(1)
+    );
+    assert!(cancelled.get());
+
+    let first = futures_executor::block_on(select_all(vec![
+        Box::new(|| {
+            yield_now();
+            0
+        }) as Box<dyn FnOnce() -> i32>,
+        Box::new(|| 2),
+    ]));
+    assert_eq!(first, (1, 2));
+}
@@ -60,9 +60,48 @@ mod page_size;
 
 mod fiber;
 pub mod generator;
+mod local;
+pub use local::{register_switch_hook, FiberLocal};
 
 #[cfg(feature = "future")]
 pub mod future;
 #[cfg(feature = "future")]
 #[doc(inline)]
-pub use future::{stackful, wait};
+pub use future::{
+    in_fiber, set_fallback, stackful, stackful_send, try_wait, wait, wait_deadline, wait_poll,
+    wait_timeout, wait_with, with_context, yield_now, Budget, Fallback, NotInFiber,
+};
+#[cfg(feature = "future")]
+mod blocking;
+#[cfg(feature = "future")]
+pub use blocking::blocking;
+#[cfg(feature = "future")]
+mod combinator;
+#[cfg(feature = "future")]
+pub use combinator::{wait_all, wait_any};
+#[cfg(feature = "future")]
+pub mod executor;
+#[cfg(feature = "future")]
+mod join;
+#[cfg(feature = "future")]
+pub use join::{join, join_all, select, select_all, Either, Join, JoinAll, Select, SelectAll};
+#[cfg(feature = "future")]
+mod scope;
+#[cfg(feature = "future")]
+pub use scope::{scope, Scope, ScopedJoinHandle};
+#[cfg(feature = "future")]
+pub mod sync;
+#[cfg(feature = "future")]
+mod timer;
+#[cfg(feature = "future")]
+pub use timer::{sleep, sleep_until, Elapsed, Interval};
+#[cfg(feature = "io")]
+pub mod io;
+#[cfg(all(feature = "future", feature = "tracing"))]
+mod trace;
+
+#[cfg(feature = "future")]
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::combinator::SelectFirst;
+}
@@ -0,0 +1,349 @@
+use core::any::Any;
+use core::cell::{Cell, RefCell};
+use core::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// State owned by a fiber, or by a thread when not running in a fiber.
+#[derive(Default)]
+pub(crate) struct LocalMap {
+    values: RefCell<Vec<(usize, Box<dyn Any>)>>,
+    // States saved by switch hooks while the fiber is suspended, indexed like `HOOKS`.
+    saved: RefCell<Vec<Option<Box<dyn Any + Send>>>>,
+}
+
+thread_local! {
+    static THREAD_MAP: LocalMap = LocalMap::default();
+    static CURRENT: Cell<*const LocalMap> = const { Cell::new(ptr::null()) };
+}
+
+trait SwitchHook: Send + Sync {
+    fn save(&self) -> Box<dyn Any + Send>;
+    fn restore(&self, state: Box<dyn Any + Send>);
+}
+
+struct FnHook<S> {
+    save: fn() -> S,
+    restore: fn(S),
+}
+
+impl<S: Send + 'static> SwitchHook for FnHook<S> {
+    fn save(&self) -> Box<dyn Any + Send> {
+        Box::new((self.save)())
+    }
+
+    fn restore(&self, state: Box<dyn Any + Send>) {
+        (self.restore)(*state.downcast().unwrap())
+    }
+}
+
+const MAX_HOOKS: usize = 64;
+
+// Hooks are never unregistered, so the list is append-only. Slots below `HOOK_COUNT` are always
+// initialised, so fiber switches can read them without taking a lock.
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_HOOK: OnceLock<&'static dyn SwitchHook> = OnceLock::new();
+static HOOKS: [OnceLock<&'static dyn SwitchHook>; MAX_HOOKS] = [EMPTY_HOOK; MAX_HOOKS];
+static HOOK_COUNT: AtomicUsize = AtomicUsize::new(0);
+// Only serialises registrations.
+static REGISTER: Mutex<()> = Mutex::new(());
+
+fn hooks() -> impl Iterator<Item = &'static dyn SwitchHook> {
+    HOOKS[..HOOK_COUNT.load(Ordering::Acquire)]
+        .iter()
+        .map(|hook| *hook.get().unwrap())
+}
+
+/// Register hooks that are invoked whenever control switches into or out of a fiber.
+///
+/// This makes a thread-local that can't be replaced by [`FiberLocal`] behave as if each fiber
+/// were its own thread. `save` should take the current state out of the thread-local and leave it
+/// in the state a new thread would see; `restore` should put a previously saved state back.
+///
+/// Saved states stay with the fiber while it is suspended, so they must be `Send` for fibers
+/// that move between threads. Hooks are called on every switch of every fiber, so they should be
+/// cheap. At most 64 hooks can be registered.
+///
+/// ```
+/// use std::cell::Cell;
+///
+/// thread_local!(static DEPTH: Cell<u32> = Cell::new(0));
+///
+/// stackful::register_switch_hook(|| DEPTH.with(|d| d.replace(0)), |v| DEPTH.with(|d| d.set(v)));
+/// ```
+pub fn register_switch_hook<S: Send + 'static>(save: fn() -> S, restore: fn(S)) {
+    let hook: &'static dyn SwitchHook = Box::leak(Box::new(FnHook { save, restore }));
+    let _guard = REGISTER.lock().unwrap();
+    let count = HOOK_COUNT.load(Ordering::Relaxed);
+    assert!(count < MAX_HOOKS, "too many switch hooks registered");
+    let _ = HOOKS[count].set(hook);
+    HOOK_COUNT.store(count + 1, Ordering::Release);
+}
+
+pub(crate) struct LocalGuard {
+    map: *const LocalMap,
+    prev: *const LocalMap,
+    // States of the thread outside the fiber, to be restored on leave.
+    outer: Vec<Box<dyn Any + Send>>,
+}
+
+impl Drop for LocalGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.prev));
+        if self.outer.is_empty() {
+            return;
+        }
+        // SAFETY: The guard doesn't outlive the map that is entered.
+        let map = unsafe { &*self.map };
+        // Hooks registered while the fiber was running are picked up by the next `enter`.
+        let mut saved = core::mem::take(&mut *map.saved.borrow_mut());
+        saved.clear();
+        for (hook, outer) in hooks().zip(self.outer.drain(..)) {
+            saved.push(Some(hook.save()));
+            hook.restore(outer);
+        }
+        *map.saved.borrow_mut() = saved;
+    }
+}
+
+impl LocalMap {
+    /// Make `FiberLocal`s use this map and swap in the states saved by switch hooks, until the
+    /// returned guard is dropped.
+    pub(crate) fn enter(&self) -> LocalGuard {
+        let prev = CURRENT.with(|current| current.replace(self));
+        let mut outer = Vec::new();
+        if HOOK_COUNT.load(Ordering::Relaxed) != 0 {
+            // Don't hold the borrow while hooks run.
+            let mut saved = core::mem::take(&mut *self.saved.borrow_mut());
+            outer = hooks()
+                .enumerate()
+                .map(|(i, hook)| {
+                    let outer = hook.save();
+                    if let Some(state) = saved.get_mut(i).and_then(Option::take) {
+                        hook.restore(state);
+                    }
+                    outer
+                })
+                .collect();
+            // Keep the allocation for the next leave.
+            *self.saved.borrow_mut() = saved;
+        }
+        LocalGuard {
+            map: self,
+            prev,
+            outer,
+        }
+    }
+
+    /// Drop all values. This should be called while the map is entered, so destructors can still
+    /// access fiber-locals.
+    pub(crate) fn clear(&self) {
+        loop {
+            // Destructors may initialise new values, so keep going until nothing is left.
+            let values = core::mem::take(&mut *self.values.borrow_mut());
+            if values.is_empty() {
+                break;
+            }
+            drop(values);
+        }
+    }
+
+    // Boxes are never removed except by `clear`, so the returned pointer is stable.
+    fn get_or_init(&self, key: usize, init: impl FnOnce() -> Box<dyn Any>) -> *const dyn Any {
+        let find = || {
+            let values = self.values.borrow();
+            values
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| &**v as *const dyn Any)
+        };
+        if let Some(v) = find() {
+            return v;
+        }
+        // Don't hold the borrow during `init` as it may access other fiber-locals.
+        let value = init();
+        if let Some(v) = find() {
+            return v;
+        }
+        let ptr = &*value as *const dyn Any;
+        self.values.borrow_mut().push((key, value));
+        ptr
+    }
+}
+
+// Never inlined, so the address of the thread-local isn't cached across a fiber switch, after
+// which we may be on a different thread.
+#[inline(never)]
+fn current_map() -> *const LocalMap {
+    CURRENT.with(|current| current.get())
+}
+
+/// A key for fiber-local storage, declared with [`fiber_local!`].
+///
+/// Each `StackfulGenerator` (and so each `StackfulFuture`) gets its own copy of the value, which
+/// is created lazily on first access and dropped when the generator completes or is dropped.
+/// Outside of a fiber this behaves like a thread-local.
+///
+/// Values are not required to be `Send`, but they move with the fiber, so a fiber created with
+/// `stackful_send` must only store `Send` values.
+pub struct FiberLocal<T: 'static> {
+    init: fn() -> T,
+}
+
+impl<T: 'static> FiberLocal<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self { init }
+    }
+
+    /// Access the value of the current fiber.
+    pub fn with<F, R>(&'static self, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let current = current_map();
+        if current.is_null() {
+            THREAD_MAP.with(|map| self.with_map(map, f))
+        } else {
+            // SAFETY: The map is entered by the generator that is currently running, so it stays
+            // valid as long as we are running.
+            self.with_map(unsafe { &*current }, f)
+        }
+    }
+
+    fn with_map<F, R>(&'static self, map: &LocalMap, f: F) -> R
+    where
+        F: FnOnce(&T) -> R,
+    {
+        let value = map.get_or_init(self as *const Self as usize, || Box::new((self.init)()));
+        // SAFETY: Values are only removed when the map is cleared, which doesn't happen while
+        // the generator owning it is running.
+        f(unsafe { &*value }.downcast_ref::<T>().unwrap())
+    }
+}
+
+/// Declare fiber-local storage keys of type [`FiberLocal`].
+///
+/// The syntax is the same as `thread_local!`:
+/// ```
+/// use std::cell::Cell;
+///
+/// stackful::fiber_local! {
+///     static REQUEST_ID: Cell<u64> = Cell::new(0);
+/// }
+///
+/// REQUEST_ID.with(|id| id.set(1));
+/// ```
+#[macro_export]
+macro_rules! fiber_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr; $($rest:tt)*) => {
+        $crate::fiber_local!($(#[$attr])* $vis static $name: $t = $init);
+        $crate::fiber_local!($($rest)*);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr) => {
+        $(#[$attr])* $vis static $name: $crate::FiberLocal<$t> = $crate::FiberLocal::new({
+            fn __init() -> $t {
+                $init
+            }
+            __init
+        });
+    };
+}
+
+#[test]
+fn fiber_local() {
+    use crate::generator::*;
+    use core::pin::Pin;
+
+    crate::fiber_local!(static VALUE: Cell<i32> = Cell::new(0));
+
+    VALUE.with(|v| v.set(1));
+    let mut gen = StackfulGenerator::new(|y: &YieldHandle<i32, ()>, ()| {
+        assert_eq!(VALUE.with(Cell::get), 0);
+        VALUE.with(|v| v.set(2));
+        y.yeet(VALUE.with(Cell::get));
+        VALUE.with(Cell::get)
+    });
+    let mut gen = Pin::new(&mut gen);
+
+    assert!(matches!(
+        gen.as_mut().resume(()),
+        GeneratorState::Yielded(2)
+    ));
+    assert_eq!(VALUE.with(Cell::get), 1);
+    VALUE.with(|v| v.set(3));
+    assert!(matches!(
+        gen.as_mut().resume(()),
+        GeneratorState::Complete(2)
+    ));
+    assert_eq!(VALUE.with(Cell::get), 3);
+}
+
+#[test]
+fn fiber_local_drop() {
+    use crate::generator::*;
+    use core::pin::Pin;
+
+    struct Flag;
+    impl Drop for Flag {
+        fn drop(&mut self) {
+            DROPPED.with(|v| v.set(true));
+        }
+    }
+
+    thread_local!(static DROPPED: Cell<bool> = const { Cell::new(false) });
+    crate::fiber_local!(static VALUE: Flag = Flag);
+
+    let mut gen = StackfulGenerator::new(|y: &YieldHandle<(), ()>, ()| {
+        VALUE.with(|_| ());
+        y.yeet(());
+    });
+    assert!(matches!(
+        Pin::new(&mut gen).resume(()),
+        GeneratorState::Yielded(())
+    ));
+    assert!(!DROPPED.with(Cell::get));
+    drop(gen);
+    assert!(DROPPED.with(Cell::get));
+}
+
+#[test]
+fn switch_hook() {
+    use crate::generator::*;
+    use core::pin::Pin;
+
+    thread_local!(static VALUE: Cell<i32> = const { Cell::new(0) });
+    thread_local!(static ACTIVE: Cell<bool> = const { Cell::new(false) });
+    // The hook stays registered for the rest of the test binary, so keep it inert in other tests.
+    register_switch_hook(
+        || ACTIVE.with(Cell::get).then(|| VALUE.with(|v| v.replace(0))),
+        |s| {
+            if let Some(s) = s {
+                VALUE.with(|v| v.set(s));
+            }
+        },
+    );
+
+    ACTIVE.with(|a| a.set(true));
+    VALUE.with(|v| v.set(1));
+    let mut gen = StackfulGenerator::new(|y: &YieldHandle<i32, ()>, ()| {
+        assert_eq!(VALUE.with(Cell::get), 0);
+        VALUE.with(|v| v.set(2));
+        y.yeet(VALUE.with(Cell::get));
+        VALUE.with(Cell::get)
+    });
+    let mut gen = Pin::new(&mut gen);
+
+    assert!(matches!(
+        gen.as_mut().resume(()),
+        GeneratorState::Yielded(2)
+    ));
+    assert_eq!(VALUE.with(Cell::get), 1);
+    VALUE.with(|v| v.set(3));
+    assert!(matches!(
+        gen.as_mut().resume(()),
+        GeneratorState::Complete(2)
+    ));
+    assert_eq!(VALUE.with(Cell::get), 3);
+}
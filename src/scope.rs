@@ -0,0 +1,239 @@
+use crate::future::{CatchUnwind, StackfulFuture};
+use crate::generator::PanicPayload;
+use crate::wait;
+
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+type Slot<T> = Rc<RefCell<Option<std::thread::Result<T>>>>;
+
+struct Child<'scope> {
+    fut: CatchUnwind<'scope, ()>,
+    // Store the panic payload in the slot of the join handle.
+    on_panic: Box<dyn FnOnce(PanicPayload) + 'scope>,
+}
+
+/// A scope to spawn fibers in, created by [`scope`].
+pub struct Scope<'scope, 'env: 'scope> {
+    // Really `Child<'scope>`, but `Scope` must not have drop glue that depends on `'scope`, as it
+    // is itself borrowed for `'scope`. `scope` makes sure that all children are dropped in time.
+    children: RefCell<Vec<Child<'static>>>,
+    // Number of children that panicked and haven't been joined.
+    panicked: Cell<usize>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+/// Handle to join a fiber spawned with [`Scope::spawn`].
+pub struct ScopedJoinHandle<'scope, T> {
+    panicked: &'scope Cell<usize>,
+    slot: Slot<T>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Spawn a fiber that runs concurrently with the rest of the scope.
+    ///
+    /// The fiber makes progress whenever the body of the scope or another fiber in it calls
+    /// `wait` on a future that isn't ready.
+    pub fn spawn<F, T>(&'scope self, f: F) -> ScopedJoinHandle<'scope, T>
+    where
+        F: FnOnce() -> T + 'scope,
+        T: 'scope,
+    {
+        let slot: Slot<T> = Rc::new(RefCell::new(None));
+        let result = slot.clone();
+        let panic = slot.clone();
+        let child = Child {
+            fut: StackfulFuture::new(move || {
+                let val = f();
+                *result.borrow_mut() = Some(Ok(val));
+            })
+            .catch_unwind(),
+            on_panic: Box::new(move |p| *panic.borrow_mut() = Some(Err(p))),
+        };
+        self.children
+            .borrow_mut()
+            .push(unsafe { std::mem::transmute::<Child<'scope>, Child<'static>>(child) });
+        ScopedJoinHandle {
+            panicked: &self.panicked,
+            slot,
+        }
+    }
+
+    // Poll all fibers once, and return whether any of them completed or new ones were spawned.
+    fn poll_children(&self, cx: &mut Context<'_>) -> bool {
+        // Don't hold the borrow while polling, as children may spawn more children.
+        let mut children = std::mem::take(&mut *self.children.borrow_mut());
+        let count = children.len();
+        let mut i = 0;
+        while i < children.len() {
+            match Pin::new(&mut children[i].fut).poll(cx) {
+                Poll::Pending => i += 1,
+                Poll::Ready(result) => {
+                    let child = children.swap_remove(i);
+                    if let Err(p) = result {
+                        self.panicked.set(self.panicked.get() + 1);
+                        (child.on_panic)(p);
+                    }
+                }
+            }
+        }
+        let mut spawned = self.children.borrow_mut();
+        let progress = children.len() != count || !spawned.is_empty();
+        children.append(&mut spawned);
+        *spawned = children;
+        progress
+    }
+}
+
+impl<T> ScopedJoinHandle<'_, T> {
+    /// Wait for the fiber to finish, returning the panic payload if it panicked.
+    pub fn join(self) -> std::thread::Result<T> {
+        let result = wait(core::future::poll_fn(|_| {
+            match self.slot.borrow_mut().take() {
+                Some(result) => Poll::Ready(result),
+                // The scope polls us again whenever a fiber completes.
+                None => Poll::Pending,
+            }
+        }));
+        if result.is_err() {
+            self.panicked.set(self.panicked.get() - 1);
+        }
+        result
+    }
+
+    /// Check if the fiber has finished running.
+    pub fn is_finished(&self) -> bool {
+        self.slot.borrow().is_some()
+    }
+}
+
+// Cancel all fibers that are still running when the scope ends.
+struct Cancel<'a, 'scope, 'env>(&'a Scope<'scope, 'env>);
+
+impl Drop for Cancel<'_, '_, '_> {
+    fn drop(&mut self) {
+        loop {
+            // Cancelled fibers may spawn more fibers while unwinding.
+            let children = std::mem::take(&mut *self.0.children.borrow_mut());
+            if children.is_empty() {
+                break;
+            }
+            drop(children);
+        }
+    }
+}
+
+/// Create a scope for spawning fibers that borrow from the enclosing stack.
+///
+/// All fibers spawned in the scope run concurrently with `f` on the current thread, switching
+/// whenever one of them calls `wait`. They are all finished before `scope` returns; if `f` panics
+/// or the enclosing `StackfulFuture` is dropped, the remaining ones are cancelled instead.
+///
+/// If any fiber panicked and wasn't joined, `scope` panics after all fibers have finished.
+///
+/// ```
+/// let mut data = vec![1, 2, 3];
+/// let sum = stackful::scope(|s| {
+///     let handle = s.spawn(|| data.iter().sum::<i32>());
+///     handle.join().unwrap()
+/// });
+/// assert_eq!(sum, 6);
+/// data.push(4);
+/// ```
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+    let scope = Scope {
+        children: RefCell::new(Vec::new()),
+        panicked: Cell::new(0),
+        scope: PhantomData,
+        env: PhantomData,
+    };
+    // Declared before `body`, so that it's dropped after it.
+    let _cancel = Cancel(&scope);
+    let mut body = StackfulFuture::new(|| f(&scope));
+    let mut result = None;
+    wait(core::future::poll_fn(|cx| loop {
+        let mut progress = false;
+        if result.is_none() {
+            if let Poll::Ready(val) = Pin::new(&mut body).poll(cx) {
+                result = Some(val);
+                progress = true;
+            }
+        }
+        progress |= scope.poll_children(cx);
+        if result.is_some() && scope.children.borrow().is_empty() {
+            return Poll::Ready(());
+        }
+        if !progress {
+            return Poll::Pending;
+        }
+    }));
+    if scope.panicked.get() != 0 {
+        panic!("a scoped fiber panicked");
+    }
+    result.unwrap()
+}
+
+#[test]
+fn test_scope() {
+    use crate::{stackful, yield_now};
+
+    let log = RefCell::new(Vec::new());
+    let run = |name| {
+        let log = &log;
+        move || {
+            for i in 0..2 {
+                log.borrow_mut().push((name, i));
+                yield_now();
+            }
+            name
+        }
+    };
+    let result = futures_executor::block_on(stackful(|| {
+        scope(|s| {
+            let a = s.spawn(run("a"));
+            s.spawn(run("b"));
+            run("body")();
+            a.join().unwrap()
+        })
+    }));
+    assert_eq!(result, "a");
+    let mut log = log.into_inner();
+    assert_eq!(log[..3], [("body", 0), ("a", 0), ("b", 0)]);
+    // Every fiber runs to completion before the scope returns.
+    log.sort();
+    assert_eq!(
+        log,
+        [
+            ("a", 0),
+            ("a", 1),
+            ("b", 0),
+            ("b", 1),
+            ("body", 0),
+            ("body", 1)
+        ]
+    );
+}
+
+#[test]
+fn test_scope_panic() {
+    let result = scope(|s| {
+        let handle = s.spawn(|| panic!("child"));
+        handle.join()
+    });
+    assert!(result.is_err());
+
+    let unjoined = std::panic::catch_unwind(|| {
+        scope(|s| {
+            s.spawn(|| panic!("child"));
+        })
+    });
+    assert!(unjoined.is_err());
+}
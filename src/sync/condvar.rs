@@ -0,0 +1,219 @@
+use super::{block, block_until, MutexGuard};
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{LockResult, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+struct Waiters {
+    next_id: u64,
+    queue: VecDeque<(u64, Option<Waker>)>,
+}
+
+/// A condition variable, like `std::sync::Condvar`, to be used with [`Mutex`](super::Mutex).
+pub struct Condvar {
+    waiters: Mutex<Waiters>,
+}
+
+/// Whether a timed wait on a [`Condvar`] returned because of the timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    /// Return whether the wait timed out.
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+// Resolves once the waiter has been removed from the queue by a notification.
+struct Notified<'a> {
+    condvar: &'a Condvar,
+    id: Option<u64>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        let id = this.id.expect("polled after completion");
+        let mut waiters = this.condvar.waiters.lock().unwrap();
+        match waiters.queue.iter_mut().find(|(i, _)| *i == id) {
+            Some((_, waker)) => {
+                *waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            None => {
+                this.id = None;
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let id = match self.id {
+            Some(id) => id,
+            None => return,
+        };
+        let mut waiters = self.condvar.waiters.lock().unwrap();
+        match waiters.queue.iter().position(|(i, _)| *i == id) {
+            Some(pos) => {
+                waiters.queue.remove(pos);
+            }
+            None => {
+                // Pass on a notification we didn't get to observe.
+                drop(waiters);
+                self.condvar.notify_one();
+            }
+        }
+    }
+}
+
+impl Condvar {
+    /// Create a condition variable with no waiters.
+    pub const fn new() -> Self {
+        Self {
+            waiters: Mutex::new(Waiters {
+                next_id: 0,
+                queue: VecDeque::new(),
+            }),
+        }
+    }
+
+    // Register as a waiter before the mutex is unlocked, so no notification can be missed.
+    fn enqueue(&self) -> Notified<'_> {
+        let mut waiters = self.waiters.lock().unwrap();
+        let id = waiters.next_id;
+        waiters.next_id += 1;
+        waiters.queue.push_back((id, None));
+        Notified {
+            condvar: self,
+            id: Some(id),
+        }
+    }
+
+    /// Unlock the mutex and wait for a notification, then lock it again.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+        let mutex = guard.mutex();
+        let notified = self.enqueue();
+        drop(guard);
+        block(notified);
+        mutex.lock()
+    }
+
+    /// Wait until `condition` returns `false`.
+    pub fn wait_while<'a, T: ?Sized, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> LockResult<MutexGuard<'a, T>>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    /// Same as `wait`, but give up after `timeout`.
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+        let mutex = guard.mutex();
+        let notified = self.enqueue();
+        drop(guard);
+        let result = WaitTimeoutResult(block_until(notified, Instant::now() + timeout).is_none());
+        match mutex.lock() {
+            Ok(guard) => Ok((guard, result)),
+            Err(err) => Err(PoisonError::new((err.into_inner(), result))),
+        }
+    }
+
+    /// Same as `wait_while`, but give up after `timeout`.
+    pub fn wait_timeout_while<'a, T: ?Sized, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        timeout: Duration,
+        mut condition: F,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        while condition(&mut *guard) {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let (next, result) = self.wait_timeout(guard, timeout)?;
+            guard = next;
+            if result.timed_out() {
+                let timed_out = condition(&mut *guard);
+                return Ok((guard, WaitTimeoutResult(timed_out)));
+            }
+        }
+        Ok((guard, WaitTimeoutResult(false)))
+    }
+
+    /// Wake up one waiter.
+    pub fn notify_one(&self) {
+        let waker = self.waiters.lock().unwrap().queue.pop_front();
+        if let Some((_, Some(waker))) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Wake up all waiters.
+    pub fn notify_all(&self) {
+        let queue = std::mem::take(&mut self.waiters.lock().unwrap().queue);
+        for waker in queue.into_iter().filter_map(|(_, waker)| waker) {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Condvar").finish_non_exhaustive()
+    }
+}
+
+#[test]
+fn test_condvar() {
+    use super::Mutex;
+    use crate::yield_now;
+
+    let ready = Mutex::new(false);
+    let condvar = Condvar::new();
+    futures_executor::block_on(crate::join(
+        || {
+            let guard = condvar
+                .wait_while(ready.lock().unwrap(), |ready| !*ready)
+                .unwrap();
+            assert!(*guard);
+        },
+        || {
+            yield_now();
+            *ready.lock().unwrap() = true;
+            condvar.notify_one();
+        },
+    ));
+
+    // Outside of a fiber, the thread blocks until the timeout.
+    let (_guard, result) = condvar
+        .wait_timeout(ready.lock().unwrap(), Duration::from_millis(10))
+        .unwrap();
+    assert!(result.timed_out());
+}
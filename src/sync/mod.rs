@@ -0,0 +1,36 @@
+//! Synchronization primitives that suspend the fiber instead of blocking the thread.
+//!
+//! The types mirror their `std::sync` counterparts, so sync code can be ported by swapping
+//! imports. When called from a fiber, blocking operations `wait` until they can proceed, letting
+//! the executor run other tasks in the meantime. Otherwise they block the thread, regardless of
+//! the fallback set with `set_fallback`.
+
+mod condvar;
+pub mod mpsc;
+mod mutex;
+pub mod oneshot;
+mod rwlock;
+mod semaphore;
+
+pub use condvar::{Condvar, WaitTimeoutResult};
+pub use mutex::{Mutex, MutexGuard};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Semaphore, SemaphorePermit};
+#[doc(no_inline)]
+pub use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+
+use crate::future::{wait_with, Fallback};
+use crate::timer::Timeout;
+
+use std::future::Future;
+use std::time::Instant;
+
+// Wait for `fut` in a fiber, or block the thread otherwise.
+fn block<T>(fut: impl Future<Output = T>) -> T {
+    wait_with(fut, Fallback::BlockOn)
+}
+
+// Same as `block`, but give up at `deadline`.
+fn block_until<T>(fut: impl Future<Output = T>, deadline: Instant) -> Option<T> {
+    block(Timeout::new(fut, deadline)).ok()
+}
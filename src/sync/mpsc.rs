@@ -0,0 +1,421 @@
+//! Multi-producer, single-consumer channels, like `std::sync::mpsc`.
+
+use super::{block, block_until};
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+#[doc(no_inline)]
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+
+struct State<T> {
+    queue: VecDeque<T>,
+    // `None` for unbounded channels, and `Some(0)` for rendezvous channels.
+    bound: Option<usize>,
+    senders: usize,
+    receiver: bool,
+    recv_waker: Option<Waker>,
+    // Senders waiting for space in a bounded channel.
+    send_wakers: Vec<Waker>,
+}
+
+impl<T> State<T> {
+    fn is_full(&self) -> bool {
+        match self.bound {
+            None => false,
+            // Values are only handed to a receiver that is waiting for one.
+            Some(0) => !self.queue.is_empty() || self.recv_waker.is_none(),
+            Some(bound) => self.queue.len() >= bound,
+        }
+    }
+}
+
+struct Chan<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+impl<T> Chan<T> {
+    fn new(bound: Option<usize>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                queue: VecDeque::new(),
+                bound,
+                senders: 1,
+                receiver: true,
+                recv_waker: None,
+                send_wakers: Vec::new(),
+            })),
+        }
+    }
+
+    fn try_send(&self, val: T) -> Result<(), TrySendError<T>> {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            if !state.receiver {
+                return Err(TrySendError::Disconnected(val));
+            }
+            if state.is_full() {
+                return Err(TrySendError::Full(val));
+            }
+            state.queue.push_back(val);
+            state.recv_waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    fn send(&self, val: T) -> Result<(), SendError<T>> {
+        let mut val = Some(val);
+        block(core::future::poll_fn(|cx| {
+            match self.try_send(val.take().unwrap()) {
+                Ok(()) => Poll::Ready(Ok(())),
+                Err(TrySendError::Disconnected(v)) => Poll::Ready(Err(SendError(v))),
+                Err(TrySendError::Full(v)) => {
+                    let mut state = self.state.lock().unwrap();
+                    // Space may have been freed since `try_send` dropped the lock.
+                    if !state.is_full() || !state.receiver {
+                        drop(state);
+                        cx.waker().wake_by_ref();
+                    } else if !state.send_wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                        state.send_wakers.push(cx.waker().clone());
+                    }
+                    val = Some(v);
+                    Poll::Pending
+                }
+            }
+        }))
+    }
+}
+
+impl<T> Clone for Chan<T> {
+    fn clone(&self) -> Self {
+        self.state.lock().unwrap().senders += 1;
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Chan<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.senders -= 1;
+            if state.senders != 0 {
+                return;
+            }
+            state.recv_waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+/// The sending half of a channel created by [`channel`].
+pub struct Sender<T> {
+    chan: Chan<T>,
+}
+
+/// The sending half of a channel created by [`sync_channel`].
+pub struct SyncSender<T> {
+    chan: Chan<T>,
+}
+
+/// The receiving half of a channel.
+pub struct Receiver<T> {
+    state: Arc<Mutex<State<T>>>,
+    // Only one fiber may receive at a time, as there is a single receive waker.
+    _marker: PhantomData<Cell<()>>,
+}
+
+/// Create an unbounded channel.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let chan = Chan::new(None);
+    let state = chan.state.clone();
+    (
+        Sender { chan },
+        Receiver {
+            state,
+            _marker: PhantomData,
+        },
+    )
+}
+
+/// Create a channel that holds at most `bound` values, after which senders have to wait.
+///
+/// With a bound of 0, the channel is a rendezvous channel: each value is only sent once the
+/// receiver is waiting for it.
+pub fn sync_channel<T>(bound: usize) -> (SyncSender<T>, Receiver<T>) {
+    let chan = Chan::new(Some(bound));
+    let state = chan.state.clone();
+    (
+        SyncSender { chan },
+        Receiver {
+            state,
+            _marker: PhantomData,
+        },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Send a value, failing if the receiver has been dropped. This never waits.
+    pub fn send(&self, val: T) -> Result<(), SendError<T>> {
+        self.chan.try_send(val).map_err(|err| match err {
+            TrySendError::Disconnected(v) | TrySendError::Full(v) => SendError(v),
+        })
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+impl<T> SyncSender<T> {
+    /// Send a value, waiting for space if the channel is full.
+    pub fn send(&self, val: T) -> Result<(), SendError<T>> {
+        self.chan.send(val)
+    }
+
+    /// Send a value without waiting.
+    pub fn try_send(&self, val: T) -> Result<(), TrySendError<T>> {
+        self.chan.try_send(val)
+    }
+}
+
+impl<T> Clone for SyncSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for SyncSender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncSender").finish_non_exhaustive()
+    }
+}
+
+impl<T> Receiver<T> {
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+        match self.try_recv() {
+            Ok(val) => Poll::Ready(Ok(val)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => {
+                let mut state = self.state.lock().unwrap();
+                // A value may have been sent since `try_recv` dropped the lock.
+                if state.queue.is_empty() && state.senders != 0 {
+                    state.recv_waker = Some(cx.waker().clone());
+                    // Senders on a rendezvous channel wait for the receiver instead of for space.
+                    let wakers = if state.bound == Some(0) {
+                        std::mem::take(&mut state.send_wakers)
+                    } else {
+                        Vec::new()
+                    };
+                    drop(state);
+                    for waker in wakers {
+                        waker.wake();
+                    }
+                } else {
+                    drop(state);
+                    cx.waker().wake_by_ref();
+                }
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Wait for a value, failing if all senders are dropped and the channel is empty.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        block(core::future::poll_fn(|cx| self.poll_recv(cx)))
+    }
+
+    /// Same as `recv`, but give up after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let fut = core::future::poll_fn(|cx| self.poll_recv(cx));
+        match block_until(fut, Instant::now() + timeout) {
+            Some(Ok(val)) => Ok(val),
+            Some(Err(RecvError)) => Err(RecvTimeoutError::Disconnected),
+            None => Err(RecvTimeoutError::Timeout),
+        }
+    }
+
+    /// Return a value if one is available, without waiting.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let (val, wakers) = {
+            let mut state = self.state.lock().unwrap();
+            match state.queue.pop_front() {
+                Some(val) => (val, std::mem::take(&mut state.send_wakers)),
+                None if state.senders == 0 => return Err(TryRecvError::Disconnected),
+                None => return Err(TryRecvError::Empty),
+            }
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+        Ok(val)
+    }
+
+    /// Return an iterator that waits for values until all senders are dropped.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { rx: self }
+    }
+
+    /// Return an iterator over the values that are available, without waiting.
+    pub fn try_iter(&self) -> TryIter<'_, T> {
+        TryIter { rx: self }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let (queue, wakers) = {
+            let mut state = self.state.lock().unwrap();
+            state.receiver = false;
+            (
+                std::mem::take(&mut state.queue),
+                std::mem::take(&mut state.send_wakers),
+            )
+        };
+        drop(queue);
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+/// Iterator returned by [`Receiver::iter`].
+#[derive(Debug)]
+pub struct Iter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+/// Iterator returned by [`Receiver::try_iter`].
+#[derive(Debug)]
+pub struct TryIter<'a, T> {
+    rx: &'a Receiver<T>,
+}
+
+impl<T> Iterator for TryIter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.try_recv().ok()
+    }
+}
+
+/// Iterator returned by `Receiver::into_iter`.
+#[derive(Debug)]
+pub struct IntoIter<T> {
+    rx: Receiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.rx.recv().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { rx: self }
+    }
+}
+
+#[test]
+fn test_mpsc() {
+    use std::cell::RefCell;
+
+    let log = RefCell::new(Vec::new());
+    let (tx, rx) = sync_channel(1);
+    futures_executor::block_on(crate::join(
+        || {
+            for i in 0..3 {
+                tx.send(i).unwrap();
+                log.borrow_mut().push(("send", i));
+            }
+            drop(tx);
+        },
+        || {
+            for i in rx.iter() {
+                log.borrow_mut().push(("recv", i));
+            }
+        },
+    ));
+    let log = log.into_inner();
+    // The sender has to wait for the receiver once the channel is full.
+    assert_eq!(log[..3], [("send", 0), ("recv", 0), ("send", 1)]);
+    assert_eq!(log.len(), 6);
+
+    // A rendezvous send waits until the receiver is waiting.
+    let log = RefCell::new(Vec::new());
+    let (tx, rx) = sync_channel(0);
+    assert_eq!(tx.try_send(0), Err(TrySendError::Full(0)));
+    futures_executor::block_on(crate::join(
+        || {
+            tx.send(1).unwrap();
+            log.borrow_mut().push("send");
+        },
+        || {
+            log.borrow_mut().push("recv");
+            assert_eq!(rx.recv(), Ok(1));
+        },
+    ));
+    assert_eq!(log.into_inner(), ["recv", "send"]);
+
+    let (tx, rx) = channel();
+    assert_eq!(
+        rx.recv_timeout(Duration::from_millis(10)),
+        Err(RecvTimeoutError::Timeout)
+    );
+    tx.send(1).unwrap();
+    drop(tx);
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), [1]);
+    assert_eq!(rx.recv(), Err(RecvError));
+}
@@ -0,0 +1,213 @@
+use super::block;
+use super::semaphore::{Semaphore, SemaphorePermit};
+use crate::generator::cancelling;
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+use std::thread;
+
+/// A mutual exclusion lock, like `std::sync::Mutex`.
+///
+/// Locking suspends the fiber if the lock is held elsewhere. The lock is poisoned if a guard is
+/// dropped while panicking, the same as in `std`, but not when a fiber is unwound because it was
+/// cancelled.
+pub struct Mutex<T: ?Sized> {
+    sem: Semaphore,
+    poisoned: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+// Poisoning takes care of broken invariants, the same as in `std`.
+impl<T: ?Sized> UnwindSafe for Mutex<T> {}
+impl<T: ?Sized> RefUnwindSafe for Mutex<T> {}
+
+/// Guard returned by [`Mutex::lock`], which unlocks the mutex when dropped.
+pub struct MutexGuard<'a, T: ?Sized> {
+    lock: &'a Mutex<T>,
+    // Whether the thread was already panicking when the lock was acquired.
+    panicking: bool,
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<T> Mutex<T> {
+    /// Create an unlocked mutex.
+    pub const fn new(val: T) -> Self {
+        Self {
+            sem: Semaphore::new(1),
+            poisoned: AtomicBool::new(false),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    /// Consume the mutex and return the inner value.
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.is_poisoned();
+        let val = self.data.into_inner();
+        if poisoned {
+            Err(PoisonError::new(val))
+        } else {
+            Ok(val)
+        }
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Acquire the lock, waiting until it is available.
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        let permit = block(self.sem.acquire_async(1));
+        self.guard(permit)
+    }
+
+    /// Try to acquire the lock without waiting.
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+        match self.sem.try_acquire() {
+            Some(permit) => Ok(self.guard(permit)?),
+            None => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    fn guard(&self, permit: SemaphorePermit<'_>) -> LockResult<MutexGuard<'_, T>> {
+        // Released by the guard instead.
+        permit.forget();
+        let guard = MutexGuard {
+            lock: self,
+            panicking: thread::panicking(),
+            _marker: PhantomData,
+        };
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Check if the mutex is poisoned.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Clear the poisoned state of the mutex.
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    /// Return a mutable reference to the inner value, without locking.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.is_poisoned();
+        let val = self.data.get_mut();
+        if poisoned {
+            Err(PoisonError::new(val))
+        } else {
+            Ok(val)
+        }
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for Mutex<T> {
+    fn from(val: T) -> Self {
+        Self::new(val)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Mutex");
+        match self.try_lock() {
+            Ok(guard) => d.field("data", &&*guard),
+            Err(TryLockError::Poisoned(err)) => d.field("data", &&**err.get_ref()),
+            Err(TryLockError::WouldBlock) => d.field("data", &format_args!("<locked>")),
+        };
+        d.field("poisoned", &self.is_poisoned()).finish()
+    }
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    pub(super) fn mutex(&self) -> &'a Mutex<T> {
+        self.lock
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Unwinding to cancel a fiber is not a panic, so it does not poison the lock.
+        if !self.panicking && thread::panicking() && !cancelling() {
+            self.lock.poisoned.store(true, Ordering::Relaxed);
+        }
+        self.lock.sem.add_permits(1);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[test]
+fn test_mutex() {
+    use crate::yield_now;
+
+    let mutex = Mutex::new(0);
+    let run = || {
+        for _ in 0..2 {
+            let mut guard = mutex.lock().unwrap();
+            let val = *guard;
+            // Other fibers can't get the lock while we are suspended.
+            yield_now();
+            *guard = val + 1;
+        }
+    };
+    futures_executor::block_on(crate::join(run, run));
+    assert_eq!(*mutex.lock().unwrap(), 4);
+
+    let guard = mutex.lock().unwrap();
+    assert!(matches!(mutex.try_lock(), Err(TryLockError::WouldBlock)));
+    drop(guard);
+
+    let _ = std::panic::catch_unwind(|| {
+        let _guard = mutex.lock().unwrap();
+        panic!();
+    });
+    assert!(mutex.is_poisoned());
+    assert_eq!(mutex.into_inner().unwrap_err().into_inner(), 4);
+}
+
+#[test]
+fn test_mutex_cancel() {
+    let mutex = Mutex::new(0);
+    let cancelled = || {
+        let _guard = mutex.lock().unwrap();
+        crate::wait(core::future::pending::<()>());
+    };
+    futures_executor::block_on(crate::select(cancelled, || ()));
+    assert!(!mutex.is_poisoned());
+    assert!(mutex.try_lock().is_ok());
+}
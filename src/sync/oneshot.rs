@@ -0,0 +1,177 @@
+//! A channel for sending a single value.
+//!
+//! The receiver can be used from sync code with [`Receiver::recv`], or awaited directly.
+
+use super::{block, block_until};
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+#[doc(no_inline)]
+pub use std::sync::mpsc::{RecvError, RecvTimeoutError, TryRecvError};
+
+struct State<T> {
+    value: Option<T>,
+    // Set when either side is dropped.
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// The sending half of a oneshot channel.
+pub struct Sender<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+/// The receiving half of a oneshot channel.
+pub struct Receiver<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+/// Create a channel for sending a single value.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let state = Arc::new(Mutex::new(State {
+        value: None,
+        closed: false,
+        waker: None,
+    }));
+    (
+        Sender {
+            state: state.clone(),
+        },
+        Receiver { state },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Send the value, or give it back if the receiver has been dropped.
+    pub fn send(self, val: T) -> Result<(), T> {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return Err(val);
+            }
+            state.value = Some(val);
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    /// Check if the receiver has been dropped.
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.closed = true;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Wait for the value, failing if the sender is dropped without sending one.
+    pub fn recv(self) -> Result<T, RecvError> {
+        block(self)
+    }
+
+    /// Same as `recv`, but give up after `timeout`.
+    ///
+    /// The receiver can still be used after a timeout.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        match block_until(&mut *self, Instant::now() + timeout) {
+            Some(Ok(val)) => Ok(val),
+            Some(Err(RecvError)) => Err(RecvTimeoutError::Disconnected),
+            None => Err(RecvTimeoutError::Timeout),
+        }
+    }
+
+    /// Return the value if it has already been sent.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.state.lock().unwrap();
+        match state.value.take() {
+            Some(val) => Ok(val),
+            None if state.closed => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(val) = state.value.take() {
+            return Poll::Ready(Ok(val));
+        }
+        if state.closed {
+            return Poll::Ready(Err(RecvError));
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let val = {
+            let mut state = self.state.lock().unwrap();
+            state.closed = true;
+            state.value.take()
+        };
+        drop(val);
+    }
+}
+
+impl<T> fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Receiver").finish_non_exhaustive()
+    }
+}
+
+#[test]
+fn test_oneshot() {
+    use crate::yield_now;
+
+    let (tx, rx) = channel();
+    let (val, ()) = futures_executor::block_on(crate::join(
+        || rx.recv(),
+        || {
+            yield_now();
+            tx.send(1).unwrap();
+        },
+    ));
+    assert_eq!(val, Ok(1));
+
+    let (tx, mut rx) = channel::<i32>();
+    assert_eq!(
+        rx.recv_timeout(Duration::from_millis(10)),
+        Err(RecvTimeoutError::Timeout)
+    );
+    drop(tx);
+    assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+    let (tx, rx) = channel();
+    drop(rx);
+    assert_eq!(tx.send(1), Err(1));
+}
@@ -0,0 +1,252 @@
+use super::block;
+use super::semaphore::{Semaphore, SemaphorePermit};
+use crate::generator::cancelling;
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LockResult, PoisonError, TryLockError, TryLockResult};
+use std::thread;
+
+// Each reader holds one permit, and a writer holds all of them.
+const MAX_READERS: usize = usize::MAX >> 3;
+
+/// A reader-writer lock, like `std::sync::RwLock`.
+///
+/// Locks are granted in the order they are requested, so writers are not starved by a steady
+/// stream of readers. Only writers poison the lock, and not when their fiber is cancelled.
+pub struct RwLock<T: ?Sized> {
+    sem: Semaphore,
+    poisoned: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+// Poisoning takes care of broken invariants, the same as in `std`.
+impl<T: ?Sized> UnwindSafe for RwLock<T> {}
+impl<T: ?Sized> RefUnwindSafe for RwLock<T> {}
+
+/// Guard returned by [`RwLock::read`].
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    _marker: PhantomData<&'a T>,
+}
+
+/// Guard returned by [`RwLock::write`].
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    // Whether the thread was already panicking when the lock was acquired.
+    panicking: bool,
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<T> RwLock<T> {
+    /// Create an unlocked lock.
+    pub const fn new(val: T) -> Self {
+        Self {
+            sem: Semaphore::new(MAX_READERS),
+            poisoned: AtomicBool::new(false),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    /// Consume the lock and return the inner value.
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.is_poisoned();
+        let val = self.data.into_inner();
+        if poisoned {
+            Err(PoisonError::new(val))
+        } else {
+            Ok(val)
+        }
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// Acquire shared read access, waiting until no writer holds or is waiting for the lock.
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        let permit = block(self.sem.acquire_async(1));
+        self.read_guard(permit)
+    }
+
+    /// Try to acquire shared read access without waiting.
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+        match self.sem.try_acquire() {
+            Some(permit) => Ok(self.read_guard(permit)?),
+            None => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    /// Acquire exclusive write access, waiting until all other guards are dropped.
+    pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+        let permit = block(self.sem.acquire_async(MAX_READERS));
+        self.write_guard(permit)
+    }
+
+    /// Try to acquire exclusive write access without waiting.
+    pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+        match self.sem.try_acquire_many(MAX_READERS) {
+            Some(permit) => Ok(self.write_guard(permit)?),
+            None => Err(TryLockError::WouldBlock),
+        }
+    }
+
+    fn read_guard(&self, permit: SemaphorePermit<'_>) -> LockResult<RwLockReadGuard<'_, T>> {
+        permit.forget();
+        let guard = RwLockReadGuard {
+            lock: self,
+            _marker: PhantomData,
+        };
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    fn write_guard(&self, permit: SemaphorePermit<'_>) -> LockResult<RwLockWriteGuard<'_, T>> {
+        permit.forget();
+        let guard = RwLockWriteGuard {
+            lock: self,
+            panicking: thread::panicking(),
+            _marker: PhantomData,
+        };
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Check if the lock is poisoned.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Clear the poisoned state of the lock.
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    /// Return a mutable reference to the inner value, without locking.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.is_poisoned();
+        let val = self.data.get_mut();
+        if poisoned {
+            Err(PoisonError::new(val))
+        } else {
+            Ok(val)
+        }
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for RwLock<T> {
+    fn from(val: T) -> Self {
+        Self::new(val)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("RwLock");
+        match self.try_read() {
+            Ok(guard) => d.field("data", &&*guard),
+            Err(TryLockError::Poisoned(err)) => d.field("data", &&**err.get_ref()),
+            Err(TryLockError::WouldBlock) => d.field("data", &format_args!("<locked>")),
+        };
+        d.field("poisoned", &self.is_poisoned()).finish()
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.sem.add_permits(1);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // Unwinding to cancel a fiber is not a panic, so it does not poison the lock.
+        if !self.panicking && thread::panicking() && !cancelling() {
+            self.lock.poisoned.store(true, Ordering::Relaxed);
+        }
+        self.lock.sem.add_permits(MAX_READERS);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[test]
+fn test_rwlock() {
+    use crate::yield_now;
+    use std::cell::RefCell;
+
+    let lock = RwLock::new(0);
+    let log = RefCell::new(Vec::new());
+    let reader = |name| {
+        let lock = &lock;
+        let log = &log;
+        move || {
+            let guard = lock.read().unwrap();
+            yield_now();
+            log.borrow_mut().push((name, *guard));
+        }
+    };
+    let writer = || {
+        let mut guard = lock.write().unwrap();
+        yield_now();
+        *guard += 1;
+        log.borrow_mut().push(("w", *guard));
+    };
+    futures_executor::block_on(crate::join_all(vec![
+        Box::new(reader("a")) as Box<dyn FnOnce()>,
+        Box::new(writer),
+        // Queued behind the writer.
+        Box::new(reader("b")),
+    ]));
+    assert_eq!(*log.borrow(), [("a", 0), ("w", 1), ("b", 1)]);
+    assert!(lock.try_write().is_ok());
+}
@@ -0,0 +1,245 @@
+use super::block;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+struct Waiter {
+    id: u64,
+    permits: usize,
+    waker: Waker,
+}
+
+struct State {
+    permits: usize,
+    next_id: u64,
+    waiters: VecDeque<Waiter>,
+}
+
+impl State {
+    // Hand out permits in order, stopping at the first waiter that can't be satisfied so that
+    // large requests are not starved by small ones. The returned wakers should be woken after
+    // the lock is released.
+    #[must_use]
+    fn grant(&mut self) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+        while let Some(waiter) = self.waiters.front() {
+            if waiter.permits > self.permits {
+                break;
+            }
+            let waiter = self.waiters.pop_front().unwrap();
+            self.permits -= waiter.permits;
+            wakers.push(waiter.waker);
+        }
+        wakers
+    }
+}
+
+/// A counting semaphore.
+///
+/// Permits are handed out in the order they are requested.
+pub struct Semaphore {
+    state: Mutex<State>,
+}
+
+/// A permit acquired from a [`Semaphore`], which is released when dropped.
+pub struct SemaphorePermit<'a> {
+    sem: &'a Semaphore,
+    permits: usize,
+}
+
+impl Semaphore {
+    /// Create a semaphore with the given number of permits.
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                permits,
+                next_id: 0,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Return the number of permits that can be acquired right now.
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().permits
+    }
+
+    /// Add `n` permits to the semaphore.
+    pub fn add_permits(&self, n: usize) {
+        let wakers = {
+            let mut state = self.state.lock().unwrap();
+            state.permits += n;
+            state.grant()
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Acquire a permit, waiting until one is available.
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Acquire `n` permits at once, waiting until they are available.
+    pub fn acquire_many(&self, n: usize) -> SemaphorePermit<'_> {
+        block(self.acquire_async(n))
+    }
+
+    /// Try to acquire a permit without waiting.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    /// Try to acquire `n` permits without waiting.
+    pub fn try_acquire_many(&self, n: usize) -> Option<SemaphorePermit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.waiters.is_empty() || state.permits < n {
+            return None;
+        }
+        state.permits -= n;
+        Some(SemaphorePermit {
+            sem: self,
+            permits: n,
+        })
+    }
+
+    pub(super) fn acquire_async(&self, n: usize) -> Acquire<'_> {
+        Acquire {
+            sem: self,
+            permits: n,
+            id: None,
+        }
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("permits", &self.available_permits())
+            .finish()
+    }
+}
+
+impl SemaphorePermit<'_> {
+    /// Drop the permit without releasing it back to the semaphore.
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.sem.add_permits(self.permits);
+    }
+}
+
+impl fmt::Debug for SemaphorePermit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SemaphorePermit")
+            .field("permits", &self.permits)
+            .finish()
+    }
+}
+
+pub(super) struct Acquire<'a> {
+    sem: &'a Semaphore,
+    permits: usize,
+    // Set while queued. Once the waiter is no longer in the queue, the permits are ours.
+    id: Option<u64>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut state = this.sem.state.lock().unwrap();
+        match this.id {
+            None => {
+                if state.waiters.is_empty() && state.permits >= this.permits {
+                    state.permits -= this.permits;
+                } else {
+                    let id = state.next_id;
+                    state.next_id += 1;
+                    state.waiters.push_back(Waiter {
+                        id,
+                        permits: this.permits,
+                        waker: cx.waker().clone(),
+                    });
+                    this.id = Some(id);
+                    return Poll::Pending;
+                }
+            }
+            Some(id) => {
+                if let Some(waiter) = state.waiters.iter_mut().find(|w| w.id == id) {
+                    if !waiter.waker.will_wake(cx.waker()) {
+                        waiter.waker = cx.waker().clone();
+                    }
+                    return Poll::Pending;
+                }
+                this.id = None;
+            }
+        }
+        Poll::Ready(SemaphorePermit {
+            sem: this.sem,
+            permits: this.permits,
+        })
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let id = match self.id {
+            Some(id) => id,
+            None => return,
+        };
+        let wakers = {
+            let mut state = self.sem.state.lock().unwrap();
+            match state.waiters.iter().position(|w| w.id == id) {
+                Some(pos) => {
+                    state.waiters.remove(pos);
+                }
+                // Permits were granted but never handed out.
+                None => state.permits += self.permits,
+            }
+            // Waiters behind us may be able to proceed now.
+            state.grant()
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+#[test]
+fn test_semaphore() {
+    use crate::{join, yield_now};
+    use std::cell::Cell;
+
+    let sem = Semaphore::new(2);
+    let active = Cell::new(0);
+    let run = || {
+        let _permit = sem.acquire();
+        active.set(active.get() + 1);
+        assert!(active.get() <= 2);
+        yield_now();
+        active.set(active.get() - 1);
+    };
+    futures_executor::block_on(crate::join_all(vec![run; 4]));
+
+    let big = sem.acquire_many(2);
+    assert!(sem.try_acquire().is_none());
+    futures_executor::block_on(join(
+        || {
+            yield_now();
+            drop(big);
+        },
+        || drop(sem.acquire()),
+    ));
+    assert_eq!(sem.available_permits(), 2);
+}
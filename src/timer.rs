@@ -0,0 +1,390 @@
+use crate::future::{in_fiber, wait};
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+struct Entry {
+    fired: AtomicBool,
+    // Set when the `Delay` is dropped before the entry fired. Only changed with the queue locked.
+    cancelled: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+struct Scheduled {
+    deadline: Instant,
+    entry: Arc<Entry>,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.deadline == other.deadline
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    // Reversed so the `BinaryHeap` pops the earliest deadline first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.deadline.cmp(&self.deadline)
+    }
+}
+
+struct Queue {
+    heap: BinaryHeap<Scheduled>,
+    // Number of cancelled entries still in the heap.
+    cancelled: usize,
+}
+
+/// Timer driven by a background thread, so it works regardless of the executor.
+///
+/// This is used with every runtime rather than the runtime's own timer: a tokio runtime may be
+/// built without its time driver, which can't be detected from a `Handle`, and async-std is not a
+/// dependency.
+struct Timer {
+    queue: Mutex<Queue>,
+    condvar: Condvar,
+}
+
+impl Timer {
+    fn get() -> &'static Timer {
+        static TIMER: OnceLock<Timer> = OnceLock::new();
+        TIMER.get_or_init(|| {
+            std::thread::Builder::new()
+                .name("stackful-timer".to_owned())
+                .spawn(|| Timer::get().run())
+                .expect("failed to spawn timer thread");
+            Timer {
+                queue: Mutex::new(Queue {
+                    heap: BinaryHeap::new(),
+                    cancelled: 0,
+                }),
+                condvar: Condvar::new(),
+            }
+        })
+    }
+
+    fn run(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let mut wakers = Vec::new();
+            while queue.heap.peek().is_some_and(|s| s.deadline <= now) {
+                let entry = queue.heap.pop().unwrap().entry;
+                if entry.cancelled.load(atomic::Ordering::Relaxed) {
+                    queue.cancelled -= 1;
+                    continue;
+                }
+                entry.fired.store(true, atomic::Ordering::Release);
+                wakers.extend(entry.waker.lock().unwrap().take());
+            }
+            if !wakers.is_empty() {
+                // Wakers may run tasks inline or schedule new delays, so don't hold the lock.
+                drop(queue);
+                for waker in wakers {
+                    waker.wake();
+                }
+                queue = self.queue.lock().unwrap();
+                continue;
+            }
+            queue = match queue.heap.peek() {
+                Some(s) => {
+                    let timeout = s.deadline - now;
+                    self.condvar.wait_timeout(queue, timeout).unwrap().0
+                }
+                None => self.condvar.wait(queue).unwrap(),
+            };
+        }
+    }
+
+    fn schedule(&self, deadline: Instant, entry: Arc<Entry>) {
+        let mut queue = self.queue.lock().unwrap();
+        let earliest = match queue.heap.peek() {
+            Some(s) => deadline < s.deadline,
+            None => true,
+        };
+        queue.heap.push(Scheduled { deadline, entry });
+        if earliest {
+            self.condvar.notify_one();
+        }
+    }
+
+    fn cancel(&self, entry: &Entry) {
+        let mut queue = self.queue.lock().unwrap();
+        // Fired entries are no longer queued.
+        if entry.fired.load(atomic::Ordering::Relaxed) {
+            return;
+        }
+        entry.cancelled.store(true, atomic::Ordering::Relaxed);
+        queue.cancelled += 1;
+        // Remove cancelled entries once they make up half of the heap, so that the heap doesn't
+        // grow with delays that are dropped long before their deadline.
+        if queue.cancelled * 2 > queue.heap.len() {
+            queue
+                .heap
+                .retain(|s| !s.entry.cancelled.load(atomic::Ordering::Relaxed));
+            queue.cancelled = 0;
+        }
+    }
+}
+
+/// Future that completes at a deadline.
+pub(crate) struct Delay {
+    deadline: Instant,
+    entry: Option<Arc<Entry>>,
+}
+
+impl Delay {
+    pub(crate) fn new(deadline: Instant) -> Self {
+        Self {
+            deadline,
+            entry: None,
+        }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        match &self.entry {
+            Some(entry) => {
+                if entry.fired.load(atomic::Ordering::Acquire) {
+                    return Poll::Ready(());
+                }
+                let mut waker = entry.waker.lock().unwrap();
+                if !waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                    *waker = Some(cx.waker().clone());
+                }
+                // The timer may have fired and taken the old waker before we replaced it.
+                if entry.fired.load(atomic::Ordering::Acquire) {
+                    return Poll::Ready(());
+                }
+            }
+            None => {
+                let entry = Arc::new(Entry {
+                    fired: AtomicBool::new(false),
+                    cancelled: AtomicBool::new(false),
+                    waker: Mutex::new(Some(cx.waker().clone())),
+                });
+                Timer::get().schedule(self.deadline, entry.clone());
+                self.entry = Some(entry);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        if let Some(entry) = &self.entry {
+            entry.waker.lock().unwrap().take();
+            Timer::get().cancel(entry);
+        }
+    }
+}
+
+/// Error returned by `wait_timeout` and `wait_deadline` when the deadline has elapsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Elapsed(());
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("deadline has elapsed")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+/// Future that resolves to `Err(Elapsed)` if the inner future doesn't complete before the deadline.
+pub(crate) struct Timeout<F> {
+    fut: F,
+    delay: Delay,
+}
+
+impl<F> Timeout<F> {
+    pub(crate) fn new(fut: F, deadline: Instant) -> Self {
+        Self {
+            fut,
+            delay: Delay::new(deadline),
+        }
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `fut` is structurally pinned, and we never move it.
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Poll::Ready(val) = unsafe { Pin::new_unchecked(&mut this.fut) }.poll(cx) {
+            return Poll::Ready(Ok(val));
+        }
+        match Pin::new(&mut this.delay).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed(()))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Suspend the fiber for `duration`.
+///
+/// This uses a timer thread shared by all fibers, so it doesn't depend on any particular runtime.
+/// Outside of a fiber, this is the same as `std::thread::sleep`.
+pub fn sleep(duration: Duration) {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Suspend the fiber until `deadline`.
+///
+/// See `sleep` for details.
+pub fn sleep_until(deadline: Instant) {
+    if in_fiber() {
+        wait(Delay::new(deadline));
+    } else {
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Ticks at a fixed period, for running something periodically from sync code.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// let mut interval = stackful::Interval::new(Duration::from_millis(10));
+/// let start = interval.tick();
+/// let next = interval.tick();
+/// assert!(next - start >= Duration::from_millis(10));
+/// ```
+#[derive(Debug)]
+pub struct Interval {
+    next: Instant,
+    period: Duration,
+}
+
+impl Interval {
+    /// Create an interval whose first tick completes immediately.
+    ///
+    /// Panics if `period` is zero.
+    pub fn new(period: Duration) -> Self {
+        Self::new_at(Instant::now(), period)
+    }
+
+    /// Create an interval whose first tick completes at `start`.
+    ///
+    /// Panics if `period` is zero.
+    pub fn new_at(start: Instant, period: Duration) -> Self {
+        assert!(!period.is_zero(), "`Interval` period must be non-zero");
+        Self {
+            next: start,
+            period,
+        }
+    }
+
+    /// Sleep until the next tick, and return the time it was scheduled for.
+    ///
+    /// Ticks are spaced by `period` regardless of how long the caller takes between them. If a
+    /// tick is late by more than a period, the missed ticks are skipped rather than completed in
+    /// a burst.
+    pub fn tick(&mut self) -> Instant {
+        sleep_until(self.next);
+        let tick = self.next;
+        self.next += self.period;
+        let now = Instant::now();
+        if self.next <= now {
+            // Keep the phase, and schedule the next tick in the future.
+            let period = self.period.as_nanos();
+            let missed = (now - self.next).as_nanos() / period + 1;
+            let skip = missed.saturating_mul(period);
+            let skip = Duration::new(
+                u64::try_from(skip / 1_000_000_000).unwrap_or(u64::MAX),
+                (skip % 1_000_000_000) as u32,
+            );
+            self.next = self.next.checked_add(skip).unwrap_or(now + self.period);
+        }
+        tick
+    }
+
+    /// Make the next tick complete one period from now.
+    pub fn reset(&mut self) {
+        self.next = Instant::now() + self.period;
+    }
+
+    /// Return the period of the interval.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+#[test]
+fn delay() {
+    use std::time::Duration;
+
+    let start = Instant::now();
+    futures_executor::block_on(Delay::new(start + Duration::from_millis(100)));
+    assert!(start.elapsed() >= Duration::from_millis(100));
+}
+
+#[test]
+fn delay_cancel() {
+    let waker = futures::task::noop_waker_ref();
+    let mut cx = Context::from_waker(waker);
+    // Far enough in the future that nothing else in the tests is scheduled at this deadline.
+    let deadline = Instant::now() + Duration::from_secs(3600);
+    let queued = || {
+        let queue = Timer::get().queue.lock().unwrap();
+        queue.heap.iter().filter(|s| s.deadline == deadline).count()
+    };
+    for _ in 0..100 {
+        let mut delay = Delay::new(deadline);
+        assert!(Pin::new(&mut delay).poll(&mut cx).is_pending());
+    }
+    // Compaction keeps at most as many cancelled entries as live ones.
+    assert!(queued() < 50);
+}
+
+#[test]
+fn interval() {
+    use crate::join;
+
+    let start = Instant::now();
+    let mut interval = Interval::new_at(start, Duration::from_millis(20));
+    let ticks = futures_executor::block_on(join(
+        || (0..3).map(|_| interval.tick()).collect::<Vec<_>>(),
+        || sleep(Duration::from_millis(10)),
+    ))
+    .0;
+    // Ticks stay in phase with `start`, even if some were skipped because we ran late.
+    let on_phase = |tick: Instant| (0..20).any(|k| tick == start + Duration::from_millis(20) * k);
+    assert_eq!(ticks[0], start);
+    assert!(ticks.windows(2).all(|w| w[0] < w[1]));
+    assert!(ticks.iter().copied().all(on_phase));
+    assert!(ticks[2] - start >= Duration::from_millis(40));
+    assert!(start.elapsed() >= ticks[2] - start);
+
+    // A late tick completes immediately, and the ones missed after it are skipped.
+    std::thread::sleep(Duration::from_millis(50));
+    let late = interval.tick();
+    assert!(late - start >= Duration::from_millis(60));
+    let next = interval.tick();
+    assert!(next > late && next - start >= Duration::from_millis(100));
+    assert!(on_phase(late) && on_phase(next));
+    assert!(start.elapsed() >= next - start);
+}
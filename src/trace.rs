@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+use tracing::dispatcher;
+use tracing::span::{EnteredSpan, Id};
+use tracing::{Dispatch, Span};
+
+/// Tracing state of the code that polls a fiber, captured at the start of each poll.
+pub(crate) struct Outer {
+    span: Option<Id>,
+}
+
+impl Outer {
+    pub(crate) fn capture() -> Self {
+        Self {
+            span: Span::current().id(),
+        }
+    }
+}
+
+/// Run one poll of a fiber with `dispatch` as the default dispatcher, and return the default
+/// dispatcher of the fiber when the poll ends.
+///
+/// The dispatcher is only set for the duration of the poll, so no thread-local guard is held
+/// across a suspension, and the fiber is free to move to another thread in between.
+pub(crate) fn with_dispatch<R>(dispatch: Option<Dispatch>, f: impl FnOnce() -> R) -> (R, Dispatch) {
+    let dispatch = dispatch.unwrap_or_else(|| dispatcher::get_default(Dispatch::clone));
+    dispatcher::with_default(&dispatch, || {
+        let result = f();
+        (result, dispatcher::get_default(Dispatch::clone))
+    })
+}
+
+/// Tracing state of a fiber suspended in `wait`. Dropping it restores the state.
+pub(crate) struct Suspended {
+    dispatch: Dispatch,
+    // Spans entered inside the fiber, innermost first.
+    entered: Vec<Id>,
+}
+
+impl Suspended {
+    /// Exit spans entered inside the fiber, so they don't stay entered on the thread while the
+    /// fiber is suspended.
+    pub(crate) fn leave(outer: &Outer) -> Self {
+        let dispatch = dispatcher::get_default(Dispatch::clone);
+        let mut entered = Vec::new();
+        while let Some(id) = Span::current().id() {
+            // Stop at the poller's span, or if the subscriber doesn't track the current span.
+            if Some(&id) == outer.span.as_ref() || entered.last() == Some(&id) {
+                break;
+            }
+            dispatch.exit(&id);
+            entered.push(id);
+        }
+        Self { dispatch, entered }
+    }
+}
+
+impl Drop for Suspended {
+    fn drop(&mut self) {
+        for id in self.entered.iter().rev() {
+            self.dispatch.enter(id);
+        }
+    }
+}
+
+/// Span covering the whole lifetime of a stackful task.
+pub(crate) struct TaskSpan {
+    span: Span,
+    polls: u64,
+    suspended: Duration,
+    suspended_at: Option<Instant>,
+}
+
+impl TaskSpan {
+    pub(crate) fn new(span: Span) -> Self {
+        Self {
+            span,
+            polls: 0,
+            suspended: Duration::from_secs(0),
+            suspended_at: None,
+        }
+    }
+
+    /// Record the start of a poll and enter the span for its duration.
+    pub(crate) fn enter(&mut self) -> EnteredSpan {
+        self.polls += 1;
+        self.span.record("polls", self.polls);
+        if let Some(at) = self.suspended_at.take() {
+            self.suspended += at.elapsed();
+            self.span
+                .record("suspended_us", self.suspended.as_micros() as u64);
+        }
+        self.span.clone().entered()
+    }
+
+    /// Record the end of a poll.
+    pub(crate) fn exit(&mut self, pending: bool) {
+        if pending {
+            self.suspended_at = Some(Instant::now());
+        }
+    }
+}